@@ -14,6 +14,7 @@ extern {
     fn curl_easy_duphandle(h: uintptr_t) -> uintptr_t;
     fn curl_easy_getinfo(h: uintptr_t, inf: c_int, ptr: *mut c_void) -> c_int;
     fn curl_easy_perform(h: uintptr_t) -> c_uint;
+    fn curl_easy_pause(h: uintptr_t, bitmask: c_int) -> c_int;
     fn curl_easy_reset(h: uintptr_t);
     fn curl_easy_strerror(code: c_int) -> *const c_char;
     fn curl_easy_setopt(h: uintptr_t, option: c_int, parameter: uintptr_t) -> c_int;
@@ -23,6 +24,11 @@ extern {
     fn curl_slist_free_all(list: uintptr_t);
 }
 
+pub static PAUSE_RECV: c_int = (1<<0);
+pub static PAUSE_SEND: c_int = (1<<2);
+pub static PAUSE_ALL: c_int = (PAUSE_RECV|PAUSE_SEND);
+pub static PAUSE_CONT: c_int = 0;
+
 pub trait ToCurlOptParam {
     fn with_curl_opt_param(&self, f:|x: uintptr_t|);
 }
@@ -45,6 +51,13 @@ impl ToCurlOptParam for uint {
     }
 }
 
+// curl_off_t options, i.e. *_LARGE ones
+impl ToCurlOptParam for i64 {
+    fn with_curl_opt_param(&self, f:|x: uintptr_t|) {
+        f(*self as uintptr_t)
+    }
+}
+
 impl<T> ToCurlOptParam for *const T {
     fn with_curl_opt_param(&self, f:|x: uintptr_t|) {
         unsafe { f(mem::transmute(*self)) }
@@ -143,22 +156,31 @@ impl FromCurlInfoPtr for f64 {
     }
 }
 
+// struct curl_slist
+#[repr(C)]
+struct SList {
+    data: *const c_char,
+    next: *const SList,
+}
+
+// List is owned by the caller, i.e. it is freed here. That's
+// true for COOKIELIST and SSL_ENGINES, but not for CERTINFO.
 impl FromCurlInfoPtr for Vec<String> {
     fn from_curl_info_ptr(ptr: uintptr_t) -> Vec<String> {
         if ptr == 0 {           // dummy create :), rust use this to identify which type to use
             Vec::new()
         } else {
-            // unsafe {
-            // let head: *SList = mem::transmute(ptr);
-            // let mut p: SList = head;
-            // let mut ret : ~[String] = ~[];
-            // while p != 0 {
-            //     ret.append(CString::new(p.data, false).as_str().to_string());
-            //     p = p.next;
-            // }
-            // ret
-            // TODO: implement, free slist
-            vec!("DUMMY-INFO-SLIST-RETURN".to_string())
+            unsafe {
+                let head: *const *const SList = mem::transmute(ptr);
+                let mut ret = Vec::new();
+                let mut p = *head;
+                while !p.is_null() {
+                    ret.push(CString::new((*p).data, false).as_str().unwrap_or("").to_string());
+                    p = (*p).next;
+                }
+                curl_slist_free_all(*head as uintptr_t);
+                ret
+            }
         }
     }
 }
@@ -180,6 +202,12 @@ impl Curl {
         self.handle == 0
     }
 
+    /// Raw `CURL*` handle, for the other libcurl APIs which are
+    /// bound to an easy handle (mime, url, websocket)
+    pub fn raw_handle(&self) -> uintptr_t {
+        self.handle
+    }
+
     // FIXME: handle \x00 byte in string
     pub fn escape(&self, url: &str) -> String {
         url.with_c_str(|c_buf| {
@@ -212,6 +240,8 @@ impl Curl {
         let ret = unsafe { curl_easy_getinfo(self.handle, option, p as *mut c_void) };
         if ret == 0 {           // OK
             let val : T = unsafe { FromCurlInfoPtr::from_curl_info_ptr(mem::transmute(p)) };
+            // Holds a raw value written by libcurl, not a valid T
+            unsafe { mem::forget(t) };
             Some(val)
         } else {
             debug!("!!!! fail getinfo() ret={}", ret);
@@ -256,6 +286,11 @@ impl Curl {
         unsafe { curl_easy_setopt(self.handle, opt::PROGRESSFUNCTION, mem::transmute(f)) as int }
     }
 
+    /// Seek callback, returns one of CURL_SEEKFUNC_*
+    pub fn set_seek_func(&self, f: fn(user_data: *mut c_void, offset: i64, origin: c_int) -> c_int) -> int {
+        unsafe { curl_easy_setopt(self.handle, opt::SEEKFUNCTION, mem::transmute(f)) as int }
+    }
+
     /*
     pub fn set_callback_func<T>(&self, option: c_int, f: T) -> int {
         match option {
@@ -268,6 +303,11 @@ impl Curl {
     }
     */
 
+    /// Pauses or resumes a transfer, see `PAUSE_*`
+    pub fn pause(&self, bitmask: c_int) -> int {
+        unsafe { curl_easy_pause(self.handle, bitmask) as int }
+    }
+
     pub fn reset(&self) {
         unsafe { curl_easy_reset(self.handle) }
     }
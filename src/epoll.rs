@@ -0,0 +1,348 @@
+use libc::{c_int, c_void, size_t, ssize_t, uintptr_t};
+use std::cmp;
+use std::collections::HashSet;
+use std::io::{IoResult, IoError, OtherIoError};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicInt, SeqCst};
+use time;
+
+use easy::Curl;
+use multi::{Multi, MultiError, SocketHandler, SocketInterest, WaitRemove,
+            SOCKET_TIMEOUT, CSELECT_IN, CSELECT_OUT, CSELECT_ERR};
+
+// Kernel ABI of struct epoll_event, packed on x86-64 only
+#[cfg(target_arch = "x86_64")]
+#[repr(C, packed)]
+struct EpollEvent {
+    events: u32,
+    data: u64,
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+#[repr(C)]
+struct EpollEvent {
+    events: u32,
+    data: u64,
+}
+
+extern {
+    fn epoll_create1(flags: c_int) -> c_int;
+    fn epoll_ctl(epfd: c_int, op: c_int, fd: c_int, event: *mut EpollEvent) -> c_int;
+    fn epoll_wait(epfd: c_int, events: *mut EpollEvent, max_events: c_int, timeout: c_int) -> c_int;
+    fn close(fd: c_int) -> c_int;
+    fn pipe2(fds: *mut c_int, flags: c_int) -> c_int;
+    fn read(fd: c_int, buf: *mut c_void, count: size_t) -> ssize_t;
+    fn write(fd: c_int, buf: *const c_void, count: size_t) -> ssize_t;
+}
+
+static O_NONBLOCK: c_int = 0x800;
+static O_CLOEXEC: c_int = 0x80000;
+static EPOLL_CLOEXEC: c_int = O_CLOEXEC;
+static EPOLL_CTL_ADD: c_int = 1;
+static EPOLL_CTL_DEL: c_int = 2;
+static EPOLL_CTL_MOD: c_int = 3;
+
+static EPOLLIN: u32 = 0x001;
+static EPOLLOUT: u32 = 0x004;
+static EPOLLERR: u32 = 0x008;
+static EPOLLHUP: u32 = 0x010;
+
+/// Events handled by one `turn` at most
+static MAX_EVENTS: uint = 256;
+
+/// Called once the transfer is over with its CURLcode
+pub type Completion = proc(Curl, uint);
+
+// Socket handler of the driver multi, keeps epoll in sync
+struct Watcher {
+    epfd: c_int,
+    sockets: HashSet<c_int>,
+    // precise_time_ns of the timer expiry, -1 if there is no timer
+    deadline: Arc<AtomicInt>,
+}
+
+impl SocketHandler for Watcher {
+    fn socket(&mut self, socket: c_int, interest: SocketInterest) {
+        if interest == WaitRemove {
+            if self.sockets.remove(&socket) {
+                unsafe { epoll_ctl(self.epfd, EPOLL_CTL_DEL, socket, &mut EpollEvent { events: 0, data: 0 }); }
+            }
+            return;
+        }
+
+        let mut events = 0;
+        if interest.is_readable() {
+            events |= EPOLLIN;
+        }
+        if interest.is_writable() {
+            events |= EPOLLOUT;
+        }
+        let op = if self.sockets.insert(socket) { EPOLL_CTL_ADD } else { EPOLL_CTL_MOD };
+        let mut event = EpollEvent { events: events, data: socket as u64 };
+        if unsafe { epoll_ctl(self.epfd, op, socket, &mut event) } != 0 {
+            debug!("Can't watch socket {}: {}", socket, IoError::last_error());
+        }
+    }
+
+    fn timer(&mut self, timeout_ms: Option<uint>) {
+        let deadline = match timeout_ms {
+            Some(ms) => (time::precise_time_ns() + ms as u64 * 1000000) as int,
+            None => -1
+        };
+        self.deadline.store(deadline, SeqCst);
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        unsafe { close(self.epfd); }
+    }
+}
+
+// Non-blocking pipe, read end is watched by the driver
+struct WakePipe {
+    read: c_int,
+    write: c_int,
+}
+
+impl Drop for WakePipe {
+    fn drop(&mut self) {
+        unsafe {
+            close(self.read);
+            close(self.write);
+        }
+    }
+}
+
+/// Interrupts waiting of `EpollDriver::turn` from another thread
+#[deriving(Clone)]
+pub struct Waker {
+    pipe: Arc<WakePipe>,
+}
+
+impl Waker {
+    pub fn wake(&self) {
+        let byte = 1u8;
+        // Full pipe means the driver is going to wake up anyway
+        unsafe { write(self.pipe.write, &byte as *const u8 as *const c_void, 1); }
+    }
+}
+
+/// Reference event loop for the socket interface of `Multi`
+///
+/// Runs any number of transfers on the calling thread, waiting
+/// with epoll on the sockets libcurl asks for. Each transfer has
+/// a completion called with the handle and the CURLcode from
+/// `turn` once it is over.
+pub struct EpollDriver {
+    // Owns the watcher, so epoll is closed after the multi
+    multi: Multi,
+    epfd: c_int,
+    deadline: Arc<AtomicInt>,
+    wake: Arc<WakePipe>,
+    transfers: Vec<(Curl, Completion)>,
+    // Filled by epoll_wait, allocated once
+    events: Vec<EpollEvent>,
+}
+
+impl EpollDriver {
+    pub fn new() -> IoResult<EpollDriver> {
+        let epfd = unsafe { epoll_create1(EPOLL_CLOEXEC) };
+        if epfd < 0 {
+            return Err(IoError::last_error());
+        }
+
+        let mut fds: [c_int, ..2] = [0, 0];
+        if unsafe { pipe2(fds.as_mut_ptr(), O_NONBLOCK | O_CLOEXEC) } != 0 {
+            let err = IoError::last_error();
+            unsafe { close(epfd); }
+            return Err(err);
+        }
+        let wake = Arc::new(WakePipe { read: fds[0], write: fds[1] });
+        let mut event = EpollEvent { events: EPOLLIN, data: wake.read as u64 };
+        if unsafe { epoll_ctl(epfd, EPOLL_CTL_ADD, wake.read, &mut event) } != 0 {
+            let err = IoError::last_error();
+            unsafe { close(epfd); }
+            return Err(err);
+        }
+
+        let deadline = Arc::new(AtomicInt::new(-1));
+        let mut multi = Multi::new();
+        let watcher = box Watcher {
+            epfd: epfd,
+            sockets: HashSet::new(),
+            deadline: deadline.clone(),
+        };
+        match multi.set_socket_handler(watcher) {
+            Ok(()) => (),
+            Err(e) => return Err(IoError {
+                kind: OtherIoError,
+                desc: "can't set socket handler",
+                detail: Some(e.message),
+            })
+        }
+
+        Ok(EpollDriver {
+            multi: multi,
+            epfd: epfd,
+            deadline: deadline,
+            wake: wake,
+            transfers: Vec::new(),
+            events: Vec::from_fn(MAX_EVENTS, |_| EpollEvent { events: 0, data: 0 }),
+        })
+    }
+
+    pub fn waker(&self) -> Waker {
+        Waker { pipe: self.wake.clone() }
+    }
+
+    /// Starts the transfer, options of `curl` are set by the caller
+    /// and data it points to must stay alive until completion
+    pub fn add(&mut self, curl: Curl, done: Completion) -> Result<(), MultiError> {
+        try!(self.multi.add(&curl));
+        self.transfers.push((curl, done));
+        Ok(())
+    }
+
+    /// Aborts the transfer, its completion is not called
+    pub fn remove(&mut self, handle: uintptr_t) -> Option<Curl> {
+        self.take(handle).map(|(curl, _)| {
+            let _ = self.multi.remove(&curl);
+            curl
+        })
+    }
+
+    /// Transfers which are not complete yet
+    pub fn len(&self) -> uint {
+        self.transfers.len()
+    }
+
+    /// Waits for socket events, the timer or a `Waker` for at most `max_wait_ms`,
+    /// lets libcurl handle them and calls completions of finished
+    /// transfers. Returns number of transfers still running.
+    pub fn turn(&mut self, max_wait_ms: uint) -> Result<uint, MultiError> {
+        let deadline = self.deadline.load(SeqCst);
+        let wait_ms = if deadline < 0 {
+            max_wait_ms
+        } else {
+            let now = time::precise_time_ns() as int;
+            cmp::min(max_wait_ms, (cmp::max(deadline - now, 0) as uint + 999999) / 1000000)
+        };
+
+        let n = unsafe { epoll_wait(self.epfd, self.events.as_mut_ptr(), MAX_EVENTS as c_int, wait_ms as c_int) };
+        // EINTR is just a turn without events
+        for event in self.events.iter().take(cmp::max(n, 0) as uint) {
+            if event.data == self.wake.read as u64 {
+                let mut buf = [0u8, ..64];
+                while unsafe { read(self.wake.read, buf.as_mut_ptr() as *mut c_void, buf.len() as size_t) } > 0 {
+                }
+                continue;
+            }
+            let mut mask = 0;
+            if event.events & EPOLLIN != 0 {
+                mask |= CSELECT_IN;
+            }
+            if event.events & EPOLLOUT != 0 {
+                mask |= CSELECT_OUT;
+            }
+            if event.events & (EPOLLERR | EPOLLHUP) != 0 {
+                mask |= CSELECT_ERR;
+            }
+            try!(self.multi.socket_action(event.data as c_int, mask));
+        }
+
+        let deadline = self.deadline.load(SeqCst);
+        if deadline >= 0 && deadline <= time::precise_time_ns() as int {
+            // libcurl sets the next timer while handling this one
+            self.deadline.store(-1, SeqCst);
+            try!(self.multi.socket_action(SOCKET_TIMEOUT, 0));
+        }
+
+        loop {
+            let done = match self.multi.info_read() {
+                Some(done) => done,
+                None => break
+            };
+            match self.take(done.handle) {
+                Some((curl, completion)) => {
+                    let _ = self.multi.remove(&curl);
+                    completion(curl, done.result);
+                },
+                None => ()
+            }
+        }
+        Ok(self.transfers.len())
+    }
+
+    /// Turns until every transfer is complete
+    pub fn run(&mut self) -> Result<(), MultiError> {
+        while self.transfers.len() > 0 {
+            try!(self.turn(1000));
+        }
+        Ok(())
+    }
+
+    fn take(&mut self, handle: uintptr_t) -> Option<(Curl, Completion)> {
+        match self.transfers.iter().position(|&(ref curl, _)| curl.raw_handle() == handle) {
+            Some(pos) => self.transfers.swap_remove(pos),
+            None => None
+        }
+    }
+}
+
+impl Drop for EpollDriver {
+    fn drop(&mut self) {
+        // Aborts transfers which are still running
+        for &(ref curl, _) in self.transfers.iter() {
+            let _ = self.multi.remove(curl);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use libc::{c_void, size_t};
+    use super::EpollDriver;
+    use easy::Curl;
+    use info;
+    use opt;
+
+    fn discard(_: *mut u8, size: size_t, nmemb: size_t, _: *mut c_void) -> size_t {
+        size * nmemb
+    }
+
+    #[test]
+    fn many_transfers() {
+        let mut driver = EpollDriver::new().unwrap();
+        let (tx, rx) = channel();
+        for i in range(0u, 20) {
+            let curl = Curl::new();
+            curl.setopt(opt::URL, format!("http://httpbin.org/get?n={}", i).as_slice());
+            curl.set_data_func(opt::WRITEFUNCTION, discard);
+            let tx = tx.clone();
+            driver.add(curl, proc(curl, code) {
+                let status: Option<int> = curl.getinfo(info::RESPONSE_CODE);
+                tx.send((code, status));
+            }).unwrap();
+        }
+        // Timer drives the transfers before any socket is known
+        driver.run().unwrap();
+        assert_eq!(driver.len(), 0);
+
+        for _ in range(0u, 20) {
+            assert_eq!(rx.recv(), (0, Some(200)));
+        }
+    }
+
+    #[test]
+    fn failed_transfer() {
+        let mut driver = EpollDriver::new().unwrap();
+        let (tx, rx) = channel();
+        let curl = Curl::new();
+        curl.setopt(opt::URL, "http://nonexistent.invalid/");
+        driver.add(curl, proc(_, code) { tx.send(code) }).unwrap();
+        driver.run().unwrap();
+        assert!(rx.recv() != 0);
+    }
+}
@@ -1,11 +1,373 @@
+use std::hash::sip::SipState;
+use std::hash::Writer as HashWriter;
+use std::io::{fs, File, IoResult, MemWriter, MemReader, Truncate, Write};
+use std::rand::random;
+use std::sync::{Arc, Mutex};
+
+use http::Headers;
+
+/// Response body sink
+///
+/// Transfer calls `head` once the response head is known, feeds
+/// every chunk of the body to `write`, then calls either `finish`
+/// or `abort` depending on the transfer result.
+pub trait Handler {
+    /// Called before the first chunk of body (or before `finish`
+    /// if there is no body), an error aborts the transfer
+    #[allow(unused_variable)]
+    fn head(&mut self, status: u16, headers: &Headers) -> IoResult<()> {
+        Ok(())
+    }
+
+    /// Consumes a chunk of body, an error aborts the transfer
+    fn write(&mut self, data: &[u8]) -> IoResult<()>;
+
+    /// Called once the whole body was received
+    fn finish(&mut self) -> IoResult<()> {
+        Ok(())
+    }
+
+    /// Called if the transfer failed, partial data should be dropped
+    fn abort(&mut self) {
+    }
+
+    /// Collected body, becomes `Response::content_data`.
+    /// Sinks which send data elsewhere return `None`.
+    fn into_reader(self: Box<Self>) -> Option<Box<Reader+'static>> {
+        None
+    }
+}
+
+/// Collects the body in memory, default one
+pub struct MemoryHandler {
+    writer: MemWriter,
+}
+
+/// Writes the body to a file atomically
+///
+/// Data goes to a temporary file next to the target, which is
+/// renamed to the target once the body is complete, so readers
+/// never see partial content and a failed download keeps
+/// the previous file intact.
 pub struct FileHandler {
-    bump: int
+    path: Path,
+    tmp_path: Path,
+    file: Option<File>,
+    // Temporary file is gone once it is renamed
+    renamed: bool,
+}
+
+/// Drops the body
+pub struct DiscardHandler;
+
+/// Incremental digest for `HashHandler`
+///
+/// Only `SipState` is implemented here, cryptographic hashes
+/// come from other crates through this trait.
+pub trait Digest {
+    fn update(&mut self, data: &[u8]);
+    fn digest(&mut self) -> Vec<u8>;
+}
+
+/// Digest value shared with `HashHandler`, set once the body is complete
+#[deriving(Clone)]
+pub struct DigestResult {
+    value: Arc<Mutex<Option<Vec<u8>>>>,
+}
+
+/// Computes digest of the body, data is passed to `inner` as well
+pub struct HashHandler<D> {
+    digest: D,
+    inner: Box<Handler+Send>,
+    result: DigestResult,
+}
+
+/// Passes the body to several handlers, the first one
+/// providing a reader defines response content
+pub struct TeeHandler {
+    handlers: Vec<Box<Handler+Send>>,
+}
+
+/// Calls a closure for every chunk
+pub struct FnHandler<F> {
+    f: F,
+}
+
+impl MemoryHandler {
+    pub fn new() -> MemoryHandler {
+        MemoryHandler {
+            writer: MemWriter::new(),
+        }
+    }
+}
+
+impl Handler for MemoryHandler {
+    fn write(&mut self, data: &[u8]) -> IoResult<()> {
+        self.writer.write(data)
+    }
+
+    fn into_reader(self: Box<MemoryHandler>) -> Option<Box<Reader+'static>> {
+        let buf = self.writer.unwrap();
+        Some(box MemReader::new(buf) as Box<Reader>)
+    }
 }
 
 impl FileHandler {
-    pub fn new() -> FileHandler {
+    pub fn new(path: &Path) -> FileHandler {
+        let tmp_name = format!(".{}.{:08x}.part", path.filename_display(), random::<u32>());
         FileHandler {
-            bump: 0,
+            path: path.clone(),
+            tmp_path: path.with_filename(tmp_name),
+            file: None,
+            renamed: false,
+        }
+    }
+
+    fn file<'a>(&'a mut self) -> IoResult<&'a mut File> {
+        if self.file.is_none() {
+            self.file = Some(try!(File::open_mode(&self.tmp_path, Truncate, Write)));
         }
+        Ok(self.file.as_mut().unwrap())
+    }
+}
+
+impl Handler for FileHandler {
+    fn write(&mut self, data: &[u8]) -> IoResult<()> {
+        try!(self.file()).write(data)
+    }
+
+    fn finish(&mut self) -> IoResult<()> {
+        // Empty body still creates the file
+        try!(try!(self.file()).fsync());
+        self.file = None;
+        try!(fs::rename(&self.tmp_path, &self.path));
+        self.renamed = true;
+        Ok(())
+    }
+
+    // Also called after a failed `finish`, when the file is closed already
+    fn abort(&mut self) {
+        self.file = None;
+        if !self.renamed {
+            let _ = fs::unlink(&self.tmp_path);
+        }
+    }
+}
+
+impl Handler for DiscardHandler {
+    fn write(&mut self, _: &[u8]) -> IoResult<()> {
+        Ok(())
+    }
+}
+
+/// SipHash with zero keys, i.e. a 64-bit checksum against accidental
+/// corruption. Anyone can craft a body with a given value, so it is
+/// not suitable for verifying downloads against tampering.
+impl Digest for SipState {
+    fn update(&mut self, data: &[u8]) {
+        self.write(data);
+    }
+
+    fn digest(&mut self) -> Vec<u8> {
+        let v = self.result();
+        range(0u, 8).map(|i| (v >> (8 * (7 - i))) as u8).collect()
+    }
+}
+
+impl DigestResult {
+    /// Digest if the body was received completely
+    pub fn get(&self) -> Option<Vec<u8>> {
+        self.value.lock().clone()
+    }
+}
+
+impl<D: Digest> HashHandler<D> {
+    /// Hashes the body and drops it
+    pub fn new(digest: D) -> (HashHandler<D>, DigestResult) {
+        HashHandler::with_inner(digest, box DiscardHandler as Box<Handler+Send>)
+    }
+
+    /// Hashes the body and passes it to `inner`
+    pub fn with_inner(digest: D, inner: Box<Handler+Send>) -> (HashHandler<D>, DigestResult) {
+        let result = DigestResult { value: Arc::new(Mutex::new(None)) };
+        let handler = HashHandler {
+            digest: digest,
+            inner: inner,
+            result: result.clone(),
+        };
+        (handler, result)
+    }
+}
+
+impl<D: Digest> Handler for HashHandler<D> {
+    fn head(&mut self, status: u16, headers: &Headers) -> IoResult<()> {
+        self.inner.head(status, headers)
+    }
+
+    fn write(&mut self, data: &[u8]) -> IoResult<()> {
+        self.digest.update(data);
+        self.inner.write(data)
+    }
+
+    fn finish(&mut self) -> IoResult<()> {
+        try!(self.inner.finish());
+        *self.result.value.lock() = Some(self.digest.digest());
+        Ok(())
+    }
+
+    fn abort(&mut self) {
+        self.inner.abort()
+    }
+
+    fn into_reader(self: Box<HashHandler<D>>) -> Option<Box<Reader+'static>> {
+        let HashHandler { inner, .. } = *self;
+        inner.into_reader()
+    }
+}
+
+impl TeeHandler {
+    pub fn new(handlers: Vec<Box<Handler+Send>>) -> TeeHandler {
+        TeeHandler {
+            handlers: handlers,
+        }
+    }
+}
+
+impl Handler for TeeHandler {
+    fn head(&mut self, status: u16, headers: &Headers) -> IoResult<()> {
+        for h in self.handlers.iter_mut() {
+            try!(h.head(status, headers));
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> IoResult<()> {
+        for h in self.handlers.iter_mut() {
+            try!(h.write(data));
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> IoResult<()> {
+        for h in self.handlers.iter_mut() {
+            try!(h.finish());
+        }
+        Ok(())
+    }
+
+    fn abort(&mut self) {
+        for h in self.handlers.iter_mut() {
+            h.abort();
+        }
+    }
+
+    fn into_reader(self: Box<TeeHandler>) -> Option<Box<Reader+'static>> {
+        let mut res = None;
+        for h in self.handlers.into_iter() {
+            if res.is_none() {
+                res = h.into_reader();
+            }
+        }
+        res
+    }
+}
+
+impl<F: FnMut(&[u8]) -> IoResult<()>> FnHandler<F> {
+    pub fn new(f: F) -> FnHandler<F> {
+        FnHandler {
+            f: f,
+        }
+    }
+}
+
+impl<F: FnMut(&[u8]) -> IoResult<()>> Handler for FnHandler<F> {
+    fn write(&mut self, data: &[u8]) -> IoResult<()> {
+        (self.f)(data)
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::{Handler, MemoryHandler, FileHandler, HashHandler, TeeHandler};
+    use std::hash::sip::SipState;
+    use std::io::{fs, File, IoResult, TempDir, USER_RWX};
+    use std::sync::{Arc, Mutex};
+    use http::Headers;
+
+    #[test]
+    fn file_is_renamed_on_finish() {
+        let dir = TempDir::new("curl-handlers").unwrap();
+        let path = dir.path().join("out.bin");
+
+        let mut h = FileHandler::new(&path);
+        h.write(b"abc").unwrap();
+        assert!(!path.exists());
+        h.finish().unwrap();
+        assert_eq!(File::open(&path).read_to_end().unwrap(), b"abc".to_vec());
+        assert_eq!(fs::readdir(dir.path()).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn file_is_removed_on_abort() {
+        let dir = TempDir::new("curl-handlers").unwrap();
+        let path = dir.path().join("out.bin");
+
+        let mut h = FileHandler::new(&path);
+        h.write(b"abc").unwrap();
+        h.abort();
+        assert_eq!(fs::readdir(dir.path()).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn file_is_removed_on_failed_rename() {
+        let dir = TempDir::new("curl-handlers").unwrap();
+        let path = dir.path().join("out");
+        // Non-empty directory can't be replaced by a file
+        fs::mkdir(&path, USER_RWX).unwrap();
+        File::create(&path.join("keep")).unwrap();
+
+        let mut h = FileHandler::new(&path);
+        h.write(b"abc").unwrap();
+        assert!(h.finish().is_err());
+        h.abort();
+        assert_eq!(fs::readdir(dir.path()).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn tee_and_hash() {
+        let (hash, digest) = HashHandler::new(SipState::new());
+        let mut h = box TeeHandler::new(vec!(box hash as Box<Handler+Send>,
+                                             box MemoryHandler::new() as Box<Handler+Send>));
+        h.write(b"abc").unwrap();
+        assert!(digest.get().is_none());
+        h.finish().unwrap();
+        assert_eq!(digest.get().unwrap().len(), 8);
+        assert_eq!(h.into_reader().unwrap().read_to_end().unwrap(), b"abc".to_vec());
+    }
+
+    struct Status {
+        seen: Arc<Mutex<Option<u16>>>,
+    }
+
+    impl Handler for Status {
+        fn head(&mut self, status: u16, _: &Headers) -> IoResult<()> {
+            *self.seen.lock() = Some(status);
+            Ok(())
+        }
+
+        fn write(&mut self, _: &[u8]) -> IoResult<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn head_is_forwarded() {
+        let seen = Arc::new(Mutex::new(None));
+        let status = box Status { seen: seen.clone() } as Box<Handler+Send>;
+        let (hash, _) = HashHandler::with_inner(SipState::new(), status);
+        let mut h = TeeHandler::new(vec!(box hash as Box<Handler+Send>));
+        h.head(206, &Headers::new()).unwrap();
+        assert_eq!(*seen.lock(), Some(206));
     }
 }
@@ -0,0 +1,288 @@
+use libc::c_long;
+use std::ascii::StrAsciiExt;
+
+use easy::Curl;
+use opt;
+use super::Headers;
+
+// CURLAUTH_* bits
+static AUTH_BASIC: c_long = 1 << 0;
+static AUTH_DIGEST: c_long = 1 << 1;
+static AUTH_NEGOTIATE: c_long = 1 << 2;
+static AUTH_NTLM: c_long = 1 << 3;
+static AUTH_BEARER: c_long = 1 << 6;
+
+/// HTTP authentication scheme
+#[deriving(Clone, Show, PartialEq)]
+pub enum Scheme {
+    BasicAuth,
+    DigestAuth,
+    NtlmAuth,
+    /// SPNEGO, i.e. Kerberos or NTLM picked by GSS-API/SSPI
+    NegotiateAuth,
+    /// OAuth 2.0 token
+    BearerAuth,
+}
+
+#[deriving(Clone, Show, PartialEq)]
+pub enum Credentials {
+    UserPassword(String, String),
+    Token(String),
+    /// Credentials of the current user, i.e. Kerberos
+    /// ticket or Windows logon, for Negotiate and NTLM
+    CurrentUser,
+}
+
+/// Authentication for a request
+///
+/// If more than one scheme is allowed, libcurl first sends the
+/// request without credentials and picks the safest scheme the
+/// server offers.
+#[deriving(Clone, Show, PartialEq)]
+pub struct Auth {
+    pub schemes: Vec<Scheme>,
+    pub credentials: Credentials,
+}
+
+/// Supplies credentials once the server asks for them
+///
+/// Called when the response is 401, the request is sent again
+/// with the returned authentication. Request body which is
+/// a stream can't be sent twice, so such requests never get here.
+pub trait CredentialProvider {
+    /// `offered` are the schemes from `WWW-Authenticate`,
+    /// `None` gives up and the 401 response is returned
+    fn credentials(&mut self, url: &str, offered: &[Scheme]) -> Option<Auth>;
+}
+
+impl Auth {
+    pub fn basic(user: &str, password: &str) -> Auth {
+        Auth::user_password(vec!(BasicAuth), user, password)
+    }
+
+    pub fn digest(user: &str, password: &str) -> Auth {
+        Auth::user_password(vec!(DigestAuth), user, password)
+    }
+
+    pub fn ntlm(user: &str, password: &str) -> Auth {
+        Auth::user_password(vec!(NtlmAuth), user, password)
+    }
+
+    /// Negotiate with credentials of the current user
+    pub fn negotiate() -> Auth {
+        Auth {
+            schemes: vec!(NegotiateAuth),
+            credentials: CurrentUser,
+        }
+    }
+
+    pub fn bearer(token: &str) -> Auth {
+        Auth {
+            schemes: vec!(BearerAuth),
+            credentials: Token(token.to_string()),
+        }
+    }
+
+    /// Any scheme the server offers except Basic, which would
+    /// send the password in clear text
+    pub fn any_safe(user: &str, password: &str) -> Auth {
+        Auth::user_password(vec!(DigestAuth, NtlmAuth, NegotiateAuth), user, password)
+    }
+
+    fn user_password(schemes: Vec<Scheme>, user: &str, password: &str) -> Auth {
+        Auth {
+            schemes: schemes,
+            credentials: UserPassword(user.to_string(), password.to_string()),
+        }
+    }
+
+    // CURLOPT_HTTPAUTH bitmask
+    fn mask(&self) -> c_long {
+        scheme_mask(self.schemes.as_slice())
+    }
+
+    pub fn apply(&self, session: &Curl) {
+        Auth::reset(session);
+        session.setopt(opt::HTTPAUTH, self.mask() as int);
+        match self.credentials {
+            UserPassword(ref user, ref password) => {
+                session.setopt(opt::USERNAME, user.as_slice());
+                session.setopt(opt::PASSWORD, password.as_slice());
+            },
+            Token(ref token) => {
+                session.setopt(opt::XOAUTH2_BEARER, token.as_slice());
+            },
+            CurrentUser => {
+                // Empty user name makes libcurl use the current one
+                session.setopt(opt::USERNAME, "");
+                session.setopt(opt::PASSWORD, "");
+            },
+        }
+    }
+
+    // Session is reused, credentials of the previous request must go
+    pub fn reset(session: &Curl) {
+        session.setopt(opt::HTTPAUTH, AUTH_BASIC as int);
+        session.setopt(opt::USERNAME, 0u);
+        session.setopt(opt::PASSWORD, 0u);
+        session.setopt(opt::XOAUTH2_BEARER, 0u);
+    }
+}
+
+// CURLAUTH_* bitmask, for HTTPAUTH and PROXYAUTH
+pub fn scheme_mask(schemes: &[Scheme]) -> c_long {
+    schemes.iter().fold(0, |acc, s| acc | match *s {
+        BasicAuth => AUTH_BASIC,
+        DigestAuth => AUTH_DIGEST,
+        NtlmAuth => AUTH_NTLM,
+        NegotiateAuth => AUTH_NEGOTIATE,
+        BearerAuth => AUTH_BEARER,
+    })
+}
+
+// Reverse of `scheme_mask`, for HTTPAUTH_AVAIL and PROXYAUTH_AVAIL
+pub fn mask_schemes(mask: c_long) -> Vec<Scheme> {
+    let all = [(AUTH_BASIC, BasicAuth), (AUTH_DIGEST, DigestAuth), (AUTH_NTLM, NtlmAuth),
+               (AUTH_NEGOTIATE, NegotiateAuth), (AUTH_BEARER, BearerAuth)];
+    all.iter().filter(|&&(bit, _)| mask & bit != 0).map(|&(_, ref s)| s.clone()).collect()
+}
+
+// Splits a header value on commas which are not inside quoted strings
+fn split_unquoted<'a>(value: &'a str) -> Vec<&'a str> {
+    let mut res = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                res.push(value.slice(start, i));
+                start = i + 1;
+            },
+            _ => ()
+        }
+    }
+    res.push(value.slice_from(start));
+    res.into_iter().map(|s| s.trim()).filter(|s| s.len() > 0).collect()
+}
+
+/// Schemes of all challenges in `WWW-Authenticate` headers
+///
+/// A header may hold several challenges separated by commas,
+/// just like parameters of a challenge, but only scheme
+/// names are not followed by `=`. Commas in quoted
+/// parameter values don't separate anything.
+pub fn offered_schemes(headers: &Headers) -> Vec<Scheme> {
+    let mut res = Vec::new();
+    let mut items = Vec::new();
+    for value in headers.get_all("WWW-Authenticate").into_iter() {
+        items.push_all(split_unquoted(value).as_slice());
+    }
+    for item in items.iter() {
+        let token = item.split(' ').next().unwrap_or("");
+        if token.contains_char('=') {
+            continue;
+        }
+        let scheme = match token.to_ascii_lower().as_slice() {
+            "basic" => BasicAuth,
+            "digest" => DigestAuth,
+            "ntlm" => NtlmAuth,
+            "negotiate" => NegotiateAuth,
+            "bearer" => BearerAuth,
+            _ => {
+                debug!("Unknown auth scheme: {}", token);
+                continue
+            }
+        };
+        if !res.contains(&scheme) {
+            res.push(scheme);
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::{Auth, CredentialProvider, Scheme, BasicAuth, DigestAuth, NtlmAuth, NegotiateAuth,
+                offered_schemes, split_unquoted, mask_schemes, AUTH_DIGEST, AUTH_NTLM, AUTH_NEGOTIATE};
+    use http::{Client, Headers, RetryPolicy};
+
+    #[test]
+    fn offered() {
+        let mut h = Headers::new();
+        h.append("WWW-Authenticate", "Digest realm=\"a, b\", nonce=\"x\", qop=\"auth\", Basic realm=\"b\"");
+        h.append("WWW-Authenticate", "Negotiate");
+        h.append("www-authenticate", "basic realm=\"c\"");
+        assert_eq!(offered_schemes(&h), vec!(DigestAuth, BasicAuth, NegotiateAuth));
+
+        // Quoted commas don't start a challenge
+        let mut h = Headers::new();
+        h.append("WWW-Authenticate", "Digest realm=\"x, Basic y\", nonce=\"a\\\"b, NTLM\"");
+        assert_eq!(offered_schemes(&h), vec!(DigestAuth));
+        assert_eq!(split_unquoted("a, b=\"c, d\" ,, e"), vec!("a", "b=\"c, d\"", "e"));
+    }
+
+    #[test]
+    fn mask() {
+        assert_eq!(Auth::any_safe("u", "p").mask(), AUTH_DIGEST | AUTH_NTLM | AUTH_NEGOTIATE);
+        assert_eq!(mask_schemes(AUTH_DIGEST | AUTH_NTLM | AUTH_NEGOTIATE),
+                   vec!(DigestAuth, NtlmAuth, NegotiateAuth));
+    }
+
+    #[test]
+    fn basic() {
+        let mut c = Client::new("http://httpbin.org/");
+        let mut req = c.new_get_request("basic-auth/user/secret");
+        assert_eq!(c.perform(&mut req).unwrap().status_code, 401);
+
+        req.auth = Some(Auth::basic("user", "secret"));
+        assert_eq!(c.perform(&mut req).unwrap().status_code, 200);
+    }
+
+    #[test]
+    fn digest() {
+        let mut c = Client::new("http://httpbin.org/");
+        c.set_auth(Auth::any_safe("user", "secret"));
+        let mut req = c.new_get_request("digest-auth/auth/user/secret");
+        assert_eq!(c.perform(&mut req).unwrap().status_code, 200);
+    }
+
+    struct Once {
+        auth: Option<Auth>,
+    }
+
+    impl CredentialProvider for Once {
+        fn credentials(&mut self, _: &str, offered: &[Scheme]) -> Option<Auth> {
+            assert_eq!(offered, [BasicAuth].as_slice());
+            self.auth.take()
+        }
+    }
+
+    #[test]
+    fn provider() {
+        let mut c = Client::new("http://httpbin.org/");
+        c.set_credential_provider(box Once { auth: Some(Auth::basic("user", "secret")) });
+        let mut req = c.new_get_request("basic-auth/user/secret");
+        assert_eq!(c.perform(&mut req).unwrap().status_code, 200);
+
+        // Provider gives up, so 401 is returned as is
+        let mut req = c.new_get_request("basic-auth/user/other");
+        let resp = c.perform(&mut req).unwrap();
+        assert_eq!(resp.status_code, 401);
+        assert_eq!(resp.auth_offered, vec!(BasicAuth));
+
+        // Authentication round is not a retry attempt
+        let mut c = Client::new("http://httpbin.org/");
+        c.set_credential_provider(box Once { auth: Some(Auth::basic("user", "secret")) });
+        c.set_retry_policy(RetryPolicy::new());
+        let mut req = c.new_get_request("basic-auth/user/secret");
+        let resp = c.perform(&mut req).unwrap();
+        assert_eq!(resp.status_code, 200);
+        assert_eq!(resp.attempts.len(), 1);
+        assert_eq!(resp.attempts[0].status, Some(200));
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::RingBuf;
+use std::mem;
+
+use easy::Curl;
+use errors::CURLE_FAILED_INIT;
+use multi::{Multi, Done};
+use super::{CurlError, Request, Response, merge_cookies};
+use super::transfer::Transfer;
+
+static WAIT_TIMEOUT_MS: uint = 1000;
+
+/// Requests performed concurrently by `Client::perform_each`
+///
+/// Iterates over `(index, result)` pairs in order the transfers
+/// complete, `index` is the position of the request in the list.
+/// Nothing is transferred in between of calls to `next`.
+/// Cookies the transfers receive go to the client as they complete.
+pub struct Batch<'a> {
+    // Client session, transfers are copies of it
+    session: &'a Curl,
+    multi: Multi,
+    // Reversed, so the first request is popped first
+    pending: Vec<(uint, Request)>,
+    // Handle goes before the data it points to
+    active: Vec<(uint, Curl, Transfer)>,
+    // Completed or failed to start, not yielded yet, oldest first
+    ready: RingBuf<(uint, Result<Response, CurlError>)>,
+    max_concurrent: uint,
+}
+
+pub fn start<'a>(session: &'a Curl, reqs: Vec<Request>, max_concurrent: uint) -> Batch<'a> {
+    let multi = Multi::new();
+    // Old libcurl doesn't multiplex, requests just use more connections then
+    let _ = multi.set_multiplex(true);
+
+    let mut pending: Vec<(uint, Request)> = reqs.into_iter().enumerate().collect();
+    pending.reverse();
+    Batch {
+        session: session,
+        multi: multi,
+        pending: pending,
+        active: Vec::new(),
+        ready: RingBuf::new(),
+        max_concurrent: max_concurrent,
+    }
+}
+
+impl<'a> Batch<'a> {
+    // Adds pending requests up to the limit
+    fn fill(&mut self) {
+        while self.max_concurrent == 0 || self.active.len() < self.max_concurrent {
+            let (index, req) = match self.pending.pop() {
+                Some(p) => p,
+                None => break
+            };
+            let curl = self.session.duphandle();
+            match Transfer::new(&curl, req) {
+                Ok(transfer) => match self.multi.add(&curl) {
+                    Ok(()) => self.active.push((index, curl, transfer)),
+                    Err(e) => self.ready.push_back((index, Err(CurlError::new(CURLE_FAILED_INIT as uint, e.message)))),
+                },
+                Err(e) => self.ready.push_back((index, Err(e))),
+            }
+        }
+    }
+
+    fn finish(&mut self, done: Done) {
+        let pos = match self.active.iter().position(|&(_, ref curl, _)| curl.raw_handle() == done.handle) {
+            Some(pos) => pos,
+            None => return
+        };
+        let (index, curl, transfer) = self.active.swap_remove(pos).unwrap();
+        let _ = self.multi.remove(&curl);
+        let result = transfer.complete(&curl, done.result);
+        merge_cookies(&curl, self.session);
+        self.ready.push_back((index, result));
+    }
+
+    // Multi handle is broken, so are transfers it runs
+    fn fail_active(&mut self, message: String) {
+        for (index, curl, _) in mem::replace(&mut self.active, Vec::new()).into_iter() {
+            let _ = self.multi.remove(&curl);
+            self.ready.push_back((index, Err(CurlError::new(CURLE_FAILED_INIT as uint, message.clone()))));
+        }
+    }
+}
+
+impl<'a> Iterator<(uint, Result<Response, CurlError>)> for Batch<'a> {
+    fn next(&mut self) -> Option<(uint, Result<Response, CurlError>)> {
+        loop {
+            self.fill();
+            match self.ready.pop_front() {
+                Some(result) => return Some(result),
+                None => ()
+            }
+            if self.active.is_empty() {
+                return None;
+            }
+
+            let running = match self.multi.perform() {
+                Ok(running) => running,
+                Err(e) => {
+                    self.fail_active(e.message);
+                    continue;
+                }
+            };
+            let mut completed = false;
+            loop {
+                match self.multi.info_read() {
+                    Some(done) => {
+                        self.finish(done);
+                        completed = true;
+                    },
+                    None => break
+                }
+            }
+            if !completed && running > 0 {
+                let _ = self.multi.wait(WAIT_TIMEOUT_MS);
+            }
+        }
+    }
+}
+
+#[unsafe_destructor]
+impl<'a> Drop for Batch<'a> {
+    fn drop(&mut self) {
+        // Aborts transfers which are still running
+        for &(_, ref curl, _) in self.active.iter() {
+            let _ = self.multi.remove(curl);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use http::{Client, Request, RedirectPolicy};
+
+    fn requests(c: &Client) -> Vec<Request> {
+        let mut reqs: Vec<Request> = range(0u, 6)
+            .map(|i| c.new_get_request(format!("get?n={}", i).as_slice()))
+            .collect();
+        reqs.push(c.new_get_request("http://nonexistent.invalid/"));
+        reqs.push(c.new_get_request("status/404"));
+        reqs
+    }
+
+    #[test]
+    fn in_order() {
+        let mut c = Client::new("http://httpbin.org/");
+        let reqs = requests(&c);
+        let results = c.perform_all(reqs, 3);
+        assert_eq!(results.len(), 8);
+
+        for (i, result) in results.into_iter().enumerate().take(6) {
+            let content = result.unwrap().content_data.unwrap().read_to_string().unwrap();
+            assert!(content.as_slice().contains(format!("\"n\": \"{}\"", i).as_slice()));
+        }
+        let results = c.perform_all(requests(&c), 0);
+        assert!(results[6].is_err());
+        assert_eq!(results[7].as_ref().unwrap().status_code, 404);
+    }
+
+    #[test]
+    fn streamed() {
+        let mut c = Client::new("http://httpbin.org/");
+        let mut reqs = vec![c.new_get_request("delay/2"), c.new_get_request("get")];
+        let mut filtered = c.new_get_request("redirect/1");
+        filtered.redirects = RedirectPolicy::same_host(5);
+        reqs.push(filtered);
+
+        let order: Vec<uint> = c.perform_each(reqs, 0).map(|(i, result)| {
+            assert_eq!(result.is_ok(), i != 2);
+            i
+        }).collect();
+        // Refused one is reported first, the slow one last
+        assert_eq!(order, vec![2, 1, 0]);
+    }
+
+    #[test]
+    fn cookies_reach_client() {
+        let mut c = Client::new("http://httpbin.org/");
+        let reqs = vec![c.new_get_request("cookies/set?batch=1")];
+        assert!(c.perform_all(reqs, 0)[0].is_ok());
+        assert!(c.cookie_jar().list().iter().any(|cookie| cookie.name.as_slice() == "batch"));
+    }
+}
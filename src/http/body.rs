@@ -0,0 +1,243 @@
+use std::cell::RefCell;
+use std::io::{File, IoResult, IoError, EndOfFile, OtherIoError, SeekSet};
+use std::{cmp, slice};
+
+use mime::Form;
+use url;
+
+/// Request body
+pub enum Body {
+    /// No body at all, `Content-Length: 0` for POST and PUT
+    Empty,
+    Bytes(Vec<u8>),
+    Text(String),
+    /// File which is opened and streamed at transfer time
+    FilePath(Path),
+    /// Reader with an optional length, chunked encoding is
+    /// used if length is unknown.
+    ///
+    /// Readers can be consumed only once, so such request
+    /// can't be performed again.
+    Stream(RefCell<Option<Box<Reader+Send>>>, Option<u64>),
+    /// `application/x-www-form-urlencoded` pairs
+    UrlEncoded(Vec<(String, String)>),
+    /// `multipart/form-data`, sent through `curl_mime`
+    Multipart(Form),
+}
+
+impl Body {
+    pub fn from_reader(reader: Box<Reader+Send>, len: Option<u64>) -> Body {
+        Stream(RefCell::new(Some(reader)), len)
+    }
+
+    pub fn from_pairs(pairs: &[(&str, &str)]) -> Body {
+        UrlEncoded(pairs.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect())
+    }
+
+    /// Content-Type which is set unless request has its own
+    pub fn default_content_type(&self) -> Option<&'static str> {
+        match *self {
+            UrlEncoded(_) => Some("application/x-www-form-urlencoded"),
+            // curl_mime sets it together with the boundary
+            _ => None
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        match *self {
+            Empty => true,
+            _ => false
+        }
+    }
+
+    /// Whether the body can be sent more than once
+    pub fn is_replayable(&self) -> bool {
+        match *self {
+            Stream(..) => false,
+            Multipart(ref form) => form.is_replayable(),
+            _ => true
+        }
+    }
+}
+
+enum Source<'a> {
+    Borrowed(&'a [u8]),
+    Owned(Vec<u8>),
+    Streamed(Box<Reader+Send>),
+    Opened(File),
+}
+
+/// Body which is being uploaded by `Client::http_read_fn`
+pub struct BodySource<'a> {
+    source: Source<'a>,
+    pos: uint,
+    /// Body length if known in advance
+    pub len: Option<u64>,
+}
+
+impl<'a> BodySource<'a> {
+    /// Prepares body for reading, `None` is returned for bodies
+    /// which don't go through the read callback (empty and multipart)
+    pub fn new(body: &'a Body) -> IoResult<Option<BodySource<'a>>> {
+        let (source, len) = match *body {
+            Empty | Multipart(_) => return Ok(None),
+            Bytes(ref bytes) => (Borrowed(bytes.as_slice()), Some(bytes.len() as u64)),
+            Text(ref text) => (Borrowed(text.as_bytes()), Some(text.len() as u64)),
+            UrlEncoded(ref pairs) => {
+                let encoded = url::encode_pairs(pairs.as_slice()).into_bytes();
+                let len = encoded.len() as u64;
+                (Owned(encoded), Some(len))
+            },
+            FilePath(ref path) => {
+                let file = try!(File::open(path));
+                let len = try!(file.stat()).size;
+                (Opened(file), Some(len))
+            },
+            Stream(ref cell, len) => match cell.borrow_mut().take() {
+                Some(reader) => (Streamed(reader), len),
+                None => return Err(IoError {
+                    kind: OtherIoError,
+                    desc: "request body reader was already consumed",
+                    detail: None
+                }),
+            },
+        };
+
+        Ok(Some(BodySource {
+            source: source,
+            pos: 0,
+            len: len,
+        }))
+    }
+
+    /// Moves to `offset` from the start, used to rewind the body
+    /// for a redirect or another authentication round
+    pub fn seek(&mut self, offset: u64) -> IoResult<()> {
+        let len = match self.source {
+            Borrowed(data) => data.len(),
+            Owned(ref data) => data.len(),
+            Opened(ref mut file) => return file.seek(offset as i64, SeekSet),
+            Streamed(_) => return Err(IoError {
+                kind: OtherIoError,
+                desc: "streamed request body can't be rewound",
+                detail: None
+            }),
+        };
+        if offset > len as u64 {
+            return Err(IoError {
+                kind: OtherIoError,
+                desc: "seek past the end of request body",
+                detail: None
+            });
+        }
+        self.pos = offset as uint;
+        Ok(())
+    }
+}
+
+impl<'a> Reader for BodySource<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        let data = match self.source {
+            Borrowed(data) => data,
+            Owned(ref data) => data.as_slice(),
+            Streamed(ref mut reader) => return reader.read(buf),
+            Opened(ref mut file) => return file.read(buf),
+        };
+
+        if self.pos >= data.len() {
+            return Err(IoError {
+                kind: EndOfFile,
+                desc: "end of body",
+                detail: None
+            });
+        }
+
+        let n = cmp::min(buf.len(), data.len() - self.pos);
+        slice::bytes::copy_memory(buf, data.slice(self.pos, self.pos + n));
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::{Body, BodySource, Bytes, Text, Multipart};
+    use http::Client;
+    use mime::Form;
+    use std::io::MemReader;
+
+    #[test]
+    fn read_bytes() {
+        let body = Bytes(vec!(1u8, 2, 3));
+        let mut source = BodySource::new(&body).unwrap().unwrap();
+        assert_eq!(source.len, Some(3));
+        assert_eq!(source.read_to_end().unwrap(), vec!(1u8, 2, 3));
+    }
+
+    #[test]
+    fn read_pairs() {
+        let body = Body::from_pairs([("a", "b c"), ("a", "&")]);
+        let mut source = BodySource::new(&body).unwrap().unwrap();
+        assert_eq!(source.read_to_end().unwrap().as_slice(), b"a=b+c&a=%26");
+    }
+
+    #[test]
+    fn rewind() {
+        let body = Text("abc".to_string());
+        let mut source = BodySource::new(&body).unwrap().unwrap();
+        assert_eq!(source.read_to_end().unwrap().as_slice(), b"abc");
+        source.seek(1).unwrap();
+        assert_eq!(source.read_to_end().unwrap().as_slice(), b"bc");
+        assert!(source.seek(4).is_err());
+
+        let body = Body::from_reader(box MemReader::new(vec!(1u8)), None);
+        assert!(BodySource::new(&body).unwrap().unwrap().seek(0).is_err());
+    }
+
+    #[test]
+    fn reader_consumed_once() {
+        let body = Body::from_reader(box MemReader::new(vec!(1u8)), None);
+        assert!(BodySource::new(&body).unwrap().unwrap().len.is_none());
+        assert!(BodySource::new(&body).is_err());
+    }
+
+    #[test]
+    fn replayable() {
+        assert!(Text("a".to_string()).is_replayable());
+        assert!(!Body::from_reader(box MemReader::new(vec!(1u8)), None).is_replayable());
+
+        let mut inner = Form::new();
+        inner.add_text("a", "b");
+        let mut form = Form::new();
+        form.add_data("blob", b"x");
+        form.add_multipart("files", inner);
+        assert!(Multipart(form).is_replayable());
+
+        let mut inner = Form::new();
+        inner.add_reader("stream", box MemReader::new(vec!(1u8)), None);
+        let mut form = Form::new();
+        form.add_multipart("files", inner);
+        assert!(!Multipart(form).is_replayable());
+    }
+
+    #[test]
+    fn multipart_upload() {
+        let mut form = Form::new();
+        form.add_text("name", "value");
+        form.add_data("blob", b"file content").filename("blob.txt").content_type("text/plain");
+        form.add_reader("stream", box MemReader::new(b"streamed".to_vec()), Some(8));
+
+        let mut c = Client::new("http://httpbin.org/");
+        let mut req = c.new_post_request("post");
+        req.set_form(form);
+        let resp = c.perform(&mut req).unwrap();
+        assert_eq!(resp.status_code, 200);
+
+        let content = resp.content_data.unwrap().read_to_string().unwrap();
+        assert!(content.as_slice().contains("\"name\": \"value\""));
+        assert!(content.as_slice().contains("\"stream\": \"streamed\""));
+        assert!(content.as_slice().contains("\"blob\": \"file content\""));
+        assert!(content.as_slice().contains("multipart/form-data; boundary="));
+    }
+}
@@ -0,0 +1,623 @@
+use serialize::base64::{ToBase64, FromBase64, STANDARD};
+use serialize::json;
+use std::ascii::StrAsciiExt;
+use std::cmp;
+use std::collections::HashMap;
+use std::hash;
+use std::io;
+use std::io::{fs, File, IoResult, Truncate, Write};
+use time;
+use time::Tm;
+
+use errors::CURLE_WRITE_ERROR;
+use handlers::{Handler, MemoryHandler};
+use super::{CurlError, Request, Response, Headers, CacheControl, Get, Head};
+
+/// Statuses which may be cached without explicit lifetime, RFC 9110 section 15.1
+static HEURISTIC_STATUSES: [u16, ..11] = [200, 203, 204, 300, 301, 308, 404, 405, 410, 414, 501];
+
+/// Headers of stored response which a 304 doesn't update
+static KEPT_HEADERS: [&'static str, ..3] = ["Content-Length", "Content-Encoding", "Transfer-Encoding"];
+
+/// How the response was obtained when `Client` has a cache
+#[deriving(Clone, Show, PartialEq)]
+pub enum CacheStatus {
+    /// Fresh stored response, no request was made
+    CacheHit,
+    /// Response came from the server
+    CacheMiss,
+    /// Stored response was confirmed by 304
+    CacheRevalidated,
+}
+
+/// What a conditional request is made with
+#[deriving(Clone, Show, PartialEq)]
+pub struct Validators {
+    /// Sent as `If-None-Match`
+    pub etag: Option<String>,
+    /// Sent as `If-Modified-Since` through `opt::TIMECONDITION`
+    pub last_modified: Option<Tm>,
+}
+
+/// Stored response
+#[deriving(Clone, Show, PartialEq)]
+pub struct CacheEntry {
+    pub url: String,
+    pub status: u16,
+    pub headers: Headers,
+    /// Request headers named by `Vary`, lowercase, empty if missing
+    pub vary: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// Size of the body as received, if it was encoded
+    pub compressed_size: Option<u64>,
+    /// When the request was sent and the response was received,
+    /// seconds since the epoch
+    pub request_time: i64,
+    pub response_time: i64,
+}
+
+/// Where `Cache` keeps responses, keyed by the request URL
+pub trait CacheStorage {
+    /// All variants stored for the key
+    fn load(&mut self, key: &str) -> Vec<CacheEntry>;
+
+    /// Replaces variants of the key, empty list removes it
+    fn store(&mut self, key: &str, entries: Vec<CacheEntry>);
+
+    fn clear(&mut self);
+}
+
+/// Keeps responses while the process runs
+pub struct MemoryStorage {
+    entries: HashMap<String, Vec<CacheEntry>>,
+}
+
+/// Keeps responses in a directory, a JSON file per URL
+pub struct DiskStorage {
+    dir: Path,
+}
+
+/// Private HTTP cache of a `Client`, see RFC 9111
+///
+/// Only GET responses are stored. Fresh ones are served without
+/// a request, stale ones are revalidated when they have `ETag`
+/// or `Last-Modified`. Request `Cache-Control` is honoured,
+/// i.e. `no-cache` forces revalidation and `no-store` bypasses
+/// the cache. Successful unsafe requests drop the stored URL.
+pub struct Cache {
+    storage: Box<CacheStorage+Send>,
+    /// Lifetime of responses without explicit one as a share
+    /// of time since `Last-Modified`
+    pub heuristic_fraction: f64,
+}
+
+fn secs(tm: Tm) -> i64 {
+    tm.to_timespec().sec
+}
+
+fn now() -> i64 {
+    time::get_time().sec
+}
+
+impl CacheEntry {
+    fn new(req_headers: &Headers, resp: &Response, body: Vec<u8>,
+           request_time: i64, response_time: i64) -> CacheEntry {
+        let vary = resp.headers.get_list("Vary").iter().map(|name| {
+            let name = name.to_ascii_lower();
+            let value = req_headers.get(name.as_slice()).unwrap_or("").to_string();
+            (name, value)
+        }).collect();
+
+        CacheEntry {
+            url: resp.url.clone(),
+            status: resp.status_code,
+            headers: resp.headers.clone(),
+            vary: vary,
+            body: body,
+            compressed_size: resp.compressed_size,
+            request_time: request_time,
+            response_time: response_time,
+        }
+    }
+
+    /// Whether it was stored for a request with such headers
+    pub fn matches(&self, req_headers: &Headers) -> bool {
+        self.vary.iter().all(|&(ref name, ref value)| {
+            name.as_slice() != "*" && req_headers.get(name.as_slice()).unwrap_or("").trim() == value.as_slice().trim()
+        })
+    }
+
+    /// Seconds the response is fresh for since it was generated
+    pub fn freshness_lifetime(&self, heuristic_fraction: f64) -> i64 {
+        match self.headers.cache_control().and_then(|cc| cc.max_age) {
+            Some(max_age) => return max_age as i64,
+            None => ()
+        }
+
+        let date = self.headers.date().map(secs).unwrap_or(self.response_time);
+        match self.headers.expires() {
+            Some(expires) => return cmp::max(secs(expires) - date, 0),
+            // Invalid date, e.g. 0, means already expired
+            None if self.headers.contains("Expires") => return 0,
+            None => ()
+        }
+
+        match self.headers.last_modified() {
+            Some(modified) if HEURISTIC_STATUSES.contains(&self.status) => {
+                cmp::max(((date - secs(modified)) as f64 * heuristic_fraction) as i64, 0)
+            },
+            _ => 0
+        }
+    }
+
+    /// Age at `now`, RFC 9111 section 4.2.3
+    pub fn current_age(&self, now: i64) -> i64 {
+        let date = self.headers.date().map(secs).unwrap_or(self.response_time);
+        let age_value = self.headers.get("Age").and_then(|v| from_str::<i64>(v.trim())).unwrap_or(0);
+        let apparent_age = cmp::max(self.response_time - date, 0);
+        let corrected_age = age_value + (self.response_time - self.request_time);
+        cmp::max(apparent_age, corrected_age) + (now - self.response_time)
+    }
+
+    pub fn validators(&self) -> Option<Validators> {
+        let etag = self.headers.etag().map(|e| e.to_string());
+        let last_modified = self.headers.last_modified();
+        if etag.is_none() && last_modified.is_none() {
+            None
+        } else {
+            Some(Validators { etag: etag, last_modified: last_modified })
+        }
+    }
+
+    // Takes headers of 304 which confirmed the entry
+    fn update(&mut self, headers: &Headers, request_time: i64, response_time: i64) {
+        let mut names: Vec<String> = headers.iter().map(|&(ref name, _)| name.to_ascii_lower()).collect();
+        names.sort();
+        names.dedup();
+        for name in names.iter().filter(|n| !KEPT_HEADERS.iter().any(|k| k.eq_ignore_ascii_case(n.as_slice()))) {
+            self.headers.remove(name.as_slice());
+            for value in headers.get_all(name.as_slice()).iter() {
+                self.headers.append(name.as_slice(), *value);
+            }
+        }
+        self.request_time = request_time;
+        self.response_time = response_time;
+    }
+
+    // Body is passed to the handler separately
+    fn to_response(&self, now: i64) -> Response {
+        let mut resp = Response::new();
+        resp.url = self.url.clone();
+        resp.status_code = self.status;
+        resp.headers = self.headers.clone();
+        resp.headers.set("Age", self.current_age(now).to_string().as_slice());
+        resp.content_encoding = self.headers.get("Content-Encoding").map(|v| v.to_string());
+        resp.compressed_size = self.compressed_size;
+        resp
+    }
+
+    fn is_storable(&self, heuristic_fraction: f64) -> bool {
+        // Not modified and partial responses are not complete ones
+        if self.status == 304 || self.status == 206 {
+            return false;
+        }
+        let cc = self.headers.cache_control();
+        if cc.as_ref().map(|cc| cc.no_store).unwrap_or(false) ||
+           self.vary.iter().any(|&(ref name, _)| name.as_slice() == "*") {
+            return false;
+        }
+
+        let explicit = cc.and_then(|cc| cc.max_age).is_some() || self.headers.contains("Expires");
+        (explicit || HEURISTIC_STATUSES.contains(&self.status)) &&
+            (self.freshness_lifetime(heuristic_fraction) > 0 || self.validators().is_some())
+    }
+}
+
+impl Cache {
+    pub fn new(storage: Box<CacheStorage+Send>) -> Cache {
+        Cache {
+            storage: storage,
+            heuristic_fraction: 0.1,
+        }
+    }
+
+    pub fn memory() -> Cache {
+        Cache::new(box MemoryStorage::new())
+    }
+
+    /// Directory is created if it doesn't exist
+    pub fn disk(dir: &Path) -> IoResult<Cache> {
+        let storage = try!(DiskStorage::new(dir));
+        Ok(Cache::new(box storage))
+    }
+
+    pub fn clear(&mut self) {
+        self.storage.clear();
+    }
+
+    fn is_fresh(&self, entry: &CacheEntry, req_cc: Option<&CacheControl>, now: i64) -> bool {
+        let mut lifetime = entry.freshness_lifetime(self.heuristic_fraction);
+        match entry.headers.cache_control() {
+            Some(ref cc) if cc.no_cache => return false,
+            _ => ()
+        }
+        match req_cc {
+            Some(cc) if cc.no_cache => return false,
+            Some(&CacheControl { max_age: Some(max_age), .. }) => lifetime = cmp::min(lifetime, max_age as i64 + 1),
+            _ => ()
+        }
+        entry.current_age(now) < lifetime
+    }
+}
+
+fn request_headers(req: &Request) -> Headers {
+    let mut headers = Headers::new();
+    for (name, value) in req.headers.iter() {
+        headers.append(name.as_slice(), value.as_slice());
+    }
+    headers
+}
+
+// Passes the body to the handler as if it was received
+fn deliver(mut handler: Box<Handler+Send>, mut resp: Response, body: &[u8]) -> Result<Response, CurlError> {
+    let res = handler.head(resp.status_code, &resp.headers)
+        .and_then(|_| if body.is_empty() { Ok(()) } else { handler.write(body) })
+        .and_then(|_| handler.finish());
+    match res {
+        Ok(()) => {
+            resp.content_data = handler.into_reader();
+            Ok(resp)
+        },
+        Err(e) => {
+            handler.abort();
+            Err(CurlError::new(CURLE_WRITE_ERROR as uint, e.to_string()))
+        }
+    }
+}
+
+// Serves `req` from the cache or through `fetch`, which performs it
+// with the given handler and validators
+pub fn perform(cache: &mut Cache, req: &Request, handler: Box<Handler+Send>,
+               fetch: |Box<Handler+Send>, Option<Validators>| -> Result<Response, CurlError>)
+               -> Result<Response, CurlError> {
+    let req_headers = request_headers(req);
+    let req_cc = req_headers.cache_control();
+    let no_store = req_cc.as_ref().map(|cc| cc.no_store).unwrap_or(false);
+
+    match req.method {
+        Get if !no_store && req.resume_from.is_none() => (),
+        Get | Head => {
+            let mut resp = try!(fetch(handler, None));
+            resp.cache_status = Some(CacheMiss);
+            return Ok(resp);
+        },
+        _ => {
+            // Unsafe methods invalidate the target, RFC 9111 section 4.4
+            let mut resp = try!(fetch(handler, None));
+            if resp.status_code < 400 {
+                cache.storage.store(req.url(), Vec::new());
+            }
+            resp.cache_status = Some(CacheMiss);
+            return Ok(resp);
+        }
+    }
+
+    let key = req.url();
+    let mut entries = cache.storage.load(key);
+    let found = entries.iter().position(|e| e.matches(&req_headers));
+
+    let validators = match found {
+        Some(idx) => {
+            let entry = &entries[idx];
+            let now = now();
+            if cache.is_fresh(entry, req_cc.as_ref(), now) {
+                debug!("Cache hit for {}", key);
+                let mut resp = entry.to_response(now);
+                resp.cache_status = Some(CacheHit);
+                return deliver(handler, resp, entry.body.as_slice());
+            }
+            entry.validators()
+        },
+        None => None
+    };
+
+    // Body is collected to be stored
+    let request_time = now();
+    let mut resp = try!(fetch(box MemoryHandler::new(), validators.clone()));
+    let response_time = now();
+    let body = match resp.content_data.take() {
+        Some(mut reader) => match reader.read_to_end() {
+            Ok(body) => body,
+            Err(e) => return Err(CurlError::new(CURLE_WRITE_ERROR as uint, e.to_string()))
+        },
+        None => Vec::new()
+    };
+
+    if resp.status_code == 304 && validators.is_some() {
+        let idx = found.unwrap();
+        entries.get_mut(idx).update(&resp.headers, request_time, response_time);
+        let entry = entries[idx].clone();
+        cache.storage.store(key, entries);
+
+        debug!("Cache entry for {} revalidated", key);
+        let mut stored = entry.to_response(response_time);
+        stored.connection = resp.connection;
+        stored.attempts = resp.attempts;
+        stored.cache_status = Some(CacheRevalidated);
+        return deliver(handler, stored, entry.body.as_slice());
+    }
+
+    resp.cache_status = Some(CacheMiss);
+    // Redirected response belongs to another URL
+    if resp.history.is_empty() {
+        let entry = CacheEntry::new(&req_headers, &resp, body.clone(), request_time, response_time);
+        if entry.is_storable(cache.heuristic_fraction) {
+            match found {
+                Some(idx) => *entries.get_mut(idx) = entry,
+                None => entries.push(entry)
+            }
+            cache.storage.store(key, entries);
+        }
+    }
+    deliver(handler, resp, body.as_slice())
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage {
+            entries: HashMap::new(),
+        }
+    }
+}
+
+impl CacheStorage for MemoryStorage {
+    fn load(&mut self, key: &str) -> Vec<CacheEntry> {
+        self.entries.find(&key.to_string()).map(|e| e.clone()).unwrap_or(Vec::new())
+    }
+
+    fn store(&mut self, key: &str, entries: Vec<CacheEntry>) {
+        if entries.is_empty() {
+            self.entries.remove(&key.to_string());
+        } else {
+            self.entries.insert(key.to_string(), entries);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+// On-disk form of `CacheEntry`
+#[deriving(Encodable, Decodable)]
+struct StoredEntry {
+    url: String,
+    status: u16,
+    headers: Vec<(String, String)>,
+    vary: Vec<(String, String)>,
+    /// Base64
+    body: String,
+    // Missing in files written by older versions
+    compressed_size: Option<u64>,
+    request_time: i64,
+    response_time: i64,
+}
+
+// Content of a file, key is kept as file names are hashes
+#[deriving(Encodable, Decodable)]
+struct StoredKey {
+    key: String,
+    entries: Vec<StoredEntry>,
+}
+
+impl DiskStorage {
+    pub fn new(dir: &Path) -> IoResult<DiskStorage> {
+        try!(fs::mkdir_recursive(dir, io::USER_RWX));
+        Ok(DiskStorage { dir: dir.clone() })
+    }
+
+    fn path(&self, key: &str) -> Path {
+        self.dir.join(format!("{:016x}.json", hash::hash(&key)))
+    }
+
+    fn read(&self, key: &str) -> IoResult<Vec<CacheEntry>> {
+        let content = try!(File::open(&self.path(key)).read_to_string());
+        let stored: StoredKey = match json::decode(content.as_slice()) {
+            Ok(stored) => stored,
+            Err(e) => {
+                debug!("Broken cache file for {}: {}", key, e);
+                return Ok(Vec::new());
+            }
+        };
+        if stored.key.as_slice() != key {
+            return Ok(Vec::new());
+        }
+
+        Ok(stored.entries.into_iter().filter_map(|e| {
+            let mut headers = Headers::new();
+            for &(ref name, ref value) in e.headers.iter() {
+                headers.append(name.as_slice(), value.as_slice());
+            }
+            e.body.as_slice().from_base64().ok().map(|body| CacheEntry {
+                url: e.url.clone(),
+                status: e.status,
+                headers: headers,
+                vary: e.vary.clone(),
+                body: body,
+                compressed_size: e.compressed_size,
+                request_time: e.request_time,
+                response_time: e.response_time,
+            })
+        }).collect())
+    }
+
+    fn write(&self, key: &str, entries: Vec<CacheEntry>) -> IoResult<()> {
+        let stored = StoredKey {
+            key: key.to_string(),
+            entries: entries.into_iter().map(|e| StoredEntry {
+                url: e.url,
+                status: e.status,
+                headers: e.headers.iter().map(|h| h.clone()).collect(),
+                vary: e.vary,
+                body: e.body.as_slice().to_base64(STANDARD),
+                compressed_size: e.compressed_size,
+                request_time: e.request_time,
+                response_time: e.response_time,
+            }).collect(),
+        };
+
+        // Written aside and renamed, so readers never see a partial file
+        let path = self.path(key);
+        let tmp_path = path.with_extension("tmp");
+        let mut file = try!(File::open_mode(&tmp_path, Truncate, Write));
+        try!(file.write_str(json::encode(&stored).as_slice()));
+        try!(file.fsync());
+        fs::rename(&tmp_path, &path)
+    }
+}
+
+impl CacheStorage for DiskStorage {
+    fn load(&mut self, key: &str) -> Vec<CacheEntry> {
+        if !self.path(key).exists() {
+            return Vec::new();
+        }
+        match self.read(key) {
+            Ok(entries) => entries,
+            Err(e) => {
+                debug!("Can't read cache file for {}: {}", key, e);
+                Vec::new()
+            }
+        }
+    }
+
+    fn store(&mut self, key: &str, entries: Vec<CacheEntry>) {
+        if entries.is_empty() {
+            let _ = fs::unlink(&self.path(key));
+            return;
+        }
+        match self.write(key, entries) {
+            Ok(()) => (),
+            Err(e) => debug!("Can't write cache file for {}: {}", key, e)
+        }
+    }
+
+    fn clear(&mut self) {
+        match fs::readdir(&self.dir) {
+            Ok(paths) => for path in paths.iter().filter(|p| p.extension() == Some(b"json")) {
+                let _ = fs::unlink(path);
+            },
+            Err(e) => debug!("Can't list cache directory: {}", e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::{Cache, CacheEntry, CacheStorage, DiskStorage, CacheHit, CacheMiss, CacheRevalidated};
+    use http::{Client, Headers};
+    use http::headers::format_http_date;
+    use std::io::TempDir;
+    use time;
+
+    fn entry(headers: &[(&str, &str)], response_time: i64) -> CacheEntry {
+        let mut h = Headers::new();
+        for &(name, value) in headers.iter() {
+            h.append(name, value);
+        }
+        CacheEntry {
+            url: "http://example.com/".to_string(),
+            status: 200,
+            headers: h,
+            vary: Vec::new(),
+            body: b"body".to_vec(),
+            compressed_size: None,
+            request_time: response_time,
+            response_time: response_time,
+        }
+    }
+
+    #[test]
+    fn freshness() {
+        let now = time::get_time().sec;
+        let date = format_http_date(&time::at_utc(time::Timespec::new(now - 100, 0)));
+
+        let e = entry([("Date", date.as_slice()), ("Cache-Control", "max-age=60")], now);
+        assert_eq!(e.freshness_lifetime(0.1), 60);
+        assert_eq!(e.current_age(now), 100);
+
+        let e = entry([("Age", "30"), ("Cache-Control", "max-age=60")], now - 10);
+        assert_eq!(e.current_age(now), 40);
+
+        let modified = format_http_date(&time::at_utc(time::Timespec::new(now - 1000, 0)));
+        let e = entry([("Date", date.as_slice()), ("Last-Modified", modified.as_slice())], now);
+        assert_eq!(e.freshness_lifetime(0.1), 90);
+
+        let e = entry([("Expires", "0"), ("ETag", "\"x\"")], now);
+        assert_eq!(e.freshness_lifetime(0.1), 0);
+        assert!(e.is_storable(0.1));
+        assert!(!entry([("Cache-Control", "no-store, max-age=60")], now).is_storable(0.1));
+
+        let mut e = entry([("Cache-Control", "max-age=60")], now);
+        e.status = 206;
+        assert!(!e.is_storable(0.1));
+        e.status = 304;
+        assert!(!e.is_storable(0.1));
+    }
+
+    #[test]
+    fn encoding_of_hit() {
+        let mut e = entry([("Content-Encoding", "gzip")], 0);
+        e.compressed_size = Some(20);
+        let resp = e.to_response(0);
+        assert_eq!(resp.content_encoding, Some("gzip".to_string()));
+        assert_eq!(resp.compressed_size, Some(20));
+    }
+
+    #[test]
+    fn vary() {
+        let mut e = entry([("Vary", "Accept-Language")], 0);
+        e.vary.push(("accept-language".to_string(), "en".to_string()));
+        let mut h = Headers::new();
+        assert!(!e.matches(&h));
+        h.append("Accept-Language", "en");
+        assert!(e.matches(&h));
+    }
+
+    #[test]
+    fn disk_storage() {
+        let dir = TempDir::new("curl-cache").unwrap();
+        let mut storage = DiskStorage::new(&dir.path().join("cache")).unwrap();
+        let e = entry([("ETag", "\"x\"")], 1000);
+        storage.store("http://example.com/", vec!(e.clone()));
+
+        let mut storage = DiskStorage::new(&dir.path().join("cache")).unwrap();
+        assert_eq!(storage.load("http://example.com/"), vec!(e));
+        assert!(storage.load("http://example.com/other").is_empty());
+
+        storage.clear();
+        assert!(storage.load("http://example.com/").is_empty());
+    }
+
+    #[test]
+    fn hit_and_revalidation() {
+        let mut c = Client::new("http://httpbin.org/");
+        c.set_cache(Some(Cache::memory()));
+
+        let mut req = c.new_get_request("cache/60");
+        let first = c.perform(&mut req).unwrap();
+        assert_eq!(first.cache_status, Some(CacheMiss));
+        let second = c.perform(&mut req).unwrap();
+        assert_eq!(second.cache_status, Some(CacheHit));
+        assert_eq!(second.content_data.unwrap().read_to_end().unwrap(),
+                   first.content_data.unwrap().read_to_end().unwrap());
+
+        // Stored with ETag but no lifetime, so it is revalidated
+        let mut req = c.new_get_request("etag/abc");
+        assert_eq!(c.perform(&mut req).unwrap().cache_status, Some(CacheMiss));
+        let resp = c.perform(&mut req).unwrap();
+        assert_eq!(resp.cache_status, Some(CacheRevalidated));
+        assert_eq!(resp.status_code, 200);
+        assert!(resp.content_data.unwrap().read_to_end().unwrap().len() > 0);
+    }
+}
@@ -0,0 +1,237 @@
+use easy;
+use easy::Curl;
+use info;
+use libc::c_int;
+use opt;
+use super::CurlError;
+
+/// Cookie as stored by libcurl cookie engine
+#[deriving(Clone, Show, PartialEq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    pub domain: String,
+    /// Cookie is sent to subdomains of `domain` as well
+    pub include_subdomains: bool,
+    pub path: String,
+    pub secure: bool,
+    pub http_only: bool,
+    /// Unix time, `None` for session cookies
+    pub expires: Option<i64>,
+}
+
+/// Cookies of a `Client`, see `Client::cookie_jar`
+///
+/// Changes affect all further requests of the client right away.
+/// Cookies are written to the jar file (if any) on `flush`
+/// and when the client is dropped.
+pub struct CookieJar<'a> {
+    session: &'a Curl,
+}
+
+static HTTP_ONLY_PREFIX: &'static str = "#HttpOnly_";
+
+impl Cookie {
+    /// Session cookie for `domain` and its subdomains, path `/`
+    pub fn new(name: &str, value: &str, domain: &str) -> Cookie {
+        Cookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            domain: domain.to_string(),
+            include_subdomains: true,
+            path: "/".to_string(),
+            secure: false,
+            http_only: false,
+            expires: None,
+        }
+    }
+
+    /// Parses a line of Netscape cookie file:
+    /// domain, subdomains, path, secure, expiry, name and value
+    /// separated by tabs
+    pub fn parse_netscape(line: &str) -> Option<Cookie> {
+        let (http_only, line) = if line.starts_with(HTTP_ONLY_PREFIX) {
+            (true, line.slice_from(HTTP_ONLY_PREFIX.len()))
+        } else if line.starts_with("#") {
+            return None;
+        } else {
+            (false, line)
+        };
+
+        let fields: Vec<&str> = line.trim_right_chars(|c: char| c == '\r' || c == '\n')
+            .split('\t').collect();
+        if fields.len() != 7 {
+            return None;
+        }
+        let expires = match from_str::<i64>(fields[4]) {
+            Some(0) => None,
+            Some(t) => Some(t),
+            None => return None
+        };
+
+        Some(Cookie {
+            name: fields[5].to_string(),
+            value: fields[6].to_string(),
+            domain: fields[0].to_string(),
+            include_subdomains: fields[1] == "TRUE",
+            path: fields[2].to_string(),
+            secure: fields[3] == "TRUE",
+            http_only: http_only,
+            expires: expires,
+        })
+    }
+
+    /// Line of Netscape cookie file, without line break
+    pub fn to_netscape(&self) -> String {
+        let flag = |b: bool| if b { "TRUE" } else { "FALSE" };
+        format!("{}{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                if self.http_only { HTTP_ONLY_PREFIX } else { "" },
+                self.domain, flag(self.include_subdomains), self.path,
+                flag(self.secure), self.expires.unwrap_or(0), self.name, self.value)
+    }
+}
+
+impl<'a> CookieJar<'a> {
+    // Client makes sure the cookie engine is on
+    pub fn new(session: &'a Curl) -> CookieJar<'a> {
+        CookieJar {
+            session: session,
+        }
+    }
+
+    /// Loads cookies from a Netscape format file (or a file
+    /// with `Set-Cookie` headers), missing file is not an error
+    pub fn load(&self, path: &Path) -> Result<(), CurlError> {
+        try!(self.set(opt::COOKIEFILE, path.as_str().unwrap_or("")));
+        self.command("RELOAD")
+    }
+
+    /// Sets file the cookies are written to
+    pub fn save_to(&self, path: &Path) -> Result<(), CurlError> {
+        self.set(opt::COOKIEJAR, path.as_str().unwrap_or(""))
+    }
+
+    /// Writes all cookies to the file set by `save_to`
+    pub fn flush(&self) -> Result<(), CurlError> {
+        self.command("FLUSH")
+    }
+
+    /// All known cookies, including expired ones
+    /// which were not purged yet
+    pub fn list(&self) -> Vec<Cookie> {
+        let lines: Option<Vec<String>> = self.session.getinfo(info::COOKIELIST);
+        lines.unwrap_or(Vec::new()).iter()
+            .filter_map(|l| Cookie::parse_netscape(l.as_slice()))
+            .collect()
+    }
+
+    /// Adds a cookie, replacing the one with the same
+    /// name, domain and path
+    pub fn add(&self, cookie: &Cookie) -> Result<(), CurlError> {
+        self.command(cookie.to_netscape().as_slice())
+    }
+
+    /// Adds a cookie the way `Set-Cookie: <header>` header
+    /// from `domain` does
+    pub fn add_header(&self, domain: &str, header: &str) -> Result<(), CurlError> {
+        let line = format!("Set-Cookie: {}; domain={}", header, domain);
+        self.command(line.as_slice())
+    }
+
+    /// Removes cookies with the given name and domain
+    pub fn remove(&self, name: &str, domain: &str) -> Result<(), CurlError> {
+        // There's no way to delete a single cookie,
+        // so the rest of them is added back
+        let cookies = self.list();
+        try!(self.clear());
+        for c in cookies.iter() {
+            if c.name.as_slice() != name || c.domain.as_slice().trim_left_chars('.') != domain.trim_left_chars('.') {
+                try!(self.add(c));
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes session cookies, i.e. ones without expiry time
+    pub fn clear_session(&self) -> Result<(), CurlError> {
+        self.command("SESS")
+    }
+
+    /// Removes all cookies
+    pub fn clear(&self) -> Result<(), CurlError> {
+        self.command("ALL")
+    }
+
+    fn command(&self, cmd: &str) -> Result<(), CurlError> {
+        self.set(opt::COOKIELIST, cmd)
+    }
+
+    fn set(&self, option: c_int, value: &str) -> Result<(), CurlError> {
+        match self.session.setopt(option, value) {
+            0 => Ok(()),
+            code => Err(CurlError::new(code as uint, easy::strerror(code)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::Cookie;
+    use http::Client;
+    use std::io::{File, TempDir};
+
+    #[test]
+    fn netscape_format() {
+        let line = "#HttpOnly_.example.com\tTRUE\t/\tTRUE\t2000000000\tid\tabc";
+        let c = Cookie::parse_netscape(line).unwrap();
+        assert_eq!(c.domain.as_slice(), ".example.com");
+        assert!(c.include_subdomains && c.secure && c.http_only);
+        assert_eq!(c.expires, Some(2000000000));
+        assert_eq!((c.name.as_slice(), c.value.as_slice()), ("id", "abc"));
+        assert_eq!(c.to_netscape().as_slice(), line);
+
+        assert!(Cookie::parse_netscape("# Netscape HTTP Cookie File").is_none());
+        assert!(Cookie::parse_netscape("example.com\tTRUE\t/").is_none());
+    }
+
+    #[test]
+    fn jar() {
+        let dir = TempDir::new("curl-cookies").unwrap();
+        let path = dir.path().join("cookies.txt");
+
+        let mut c = Client::new("http://example.com/");
+        {
+            let jar = c.cookie_jar();
+            jar.add(&Cookie::new("a", "1", "example.com")).unwrap();
+            jar.add(&Cookie::new("b", "2", "example.com")).unwrap();
+            jar.add_header("example.org", "c=3; Path=/x").unwrap();
+            assert_eq!(jar.list().len(), 3);
+
+            jar.remove("a", "example.com").unwrap();
+            let names: Vec<String> = jar.list().into_iter().map(|c| c.name).collect();
+            assert_eq!(names.len(), 2);
+            assert!(!names.contains(&"a".to_string()));
+
+            jar.save_to(&path).unwrap();
+            jar.flush().unwrap();
+        }
+        let saved = File::open(&path).read_to_string().unwrap();
+        assert!(saved.as_slice().contains("\tc\t3"));
+
+        let mut other = Client::new("http://example.com/");
+        let jar = other.cookie_jar();
+        jar.load(&path).unwrap();
+        assert_eq!(jar.list().len(), 2);
+    }
+
+    #[test]
+    fn received() {
+        let mut c = Client::new("http://httpbin.org/");
+        let mut req = c.new_get_request("cookies/set?session=xyz");
+        c.perform(&mut req).unwrap();
+
+        let cookies = c.cookie_jar().list();
+        assert!(cookies.iter().any(|c| c.name.as_slice() == "session" && c.value.as_slice() == "xyz"));
+    }
+}
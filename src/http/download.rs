@@ -0,0 +1,313 @@
+use std::io::{fs, File, IoResult, IoError, OtherIoError, Append, Truncate, Write};
+use std::sync::{Arc, Mutex};
+
+use errors::{CURLE_RANGE_ERROR, CURLE_BAD_DOWNLOAD_RESUME, CURLE_PARTIAL_FILE,
+             CURLE_OPERATION_TIMEDOUT, CURLE_RECV_ERROR, CURLE_SEND_ERROR,
+             CURLE_GOT_NOTHING, CURLE_COULDNT_CONNECT};
+use handlers::Handler;
+use libc;
+use super::{Client, CurlError, Request, Response, Headers};
+
+/// Why a download failed
+#[deriving(Show)]
+pub enum DownloadError {
+    /// `CURLE_BAD_DOWNLOAD_RESUME`, offset can't be used even
+    /// after restarting from scratch
+    BadResume(CurlError),
+    /// `CURLE_RANGE_ERROR`, server doesn't honour ranges
+    /// or returned a range other than requested
+    RangeError(CurlError),
+    /// Transfer failed and can't be retried anymore
+    TransferFailed(CurlError),
+    /// Server answered with non-successful status
+    HttpStatus(u16),
+    /// Local file can't be written or renamed
+    FileError(IoError),
+}
+
+/// Downloads to a file, resuming after interruptions
+///
+/// Data goes to `<path>.part`, the validator (ETag or Last-Modified)
+/// of the resource goes to `<path>.part.validator`. If these files
+/// exist when download starts, only the rest of the resource is
+/// requested with `If-Range`, so if it has changed meanwhile the
+/// server sends it in full and download restarts from zero.
+/// Once complete, the part file is renamed to `path`.
+pub struct Download {
+    path: Path,
+    part_path: Path,
+    validator_path: Path,
+    /// Transfer attempts made for a single `run`, including resumes
+    pub max_attempts: uint,
+}
+
+// Collected by ResumeHandler while transfer goes
+struct HeadCheck {
+    range_error: Option<String>,
+}
+
+// Appends 2xx response body to the part file
+struct ResumeHandler {
+    part_path: Path,
+    validator_path: Path,
+    offset: u64,
+    file: Option<File>,
+    check: Arc<Mutex<HeadCheck>>,
+}
+
+impl Download {
+    pub fn new(path: &Path) -> Download {
+        let mut part_name = path.filename().unwrap_or(b"download").to_vec();
+        part_name.push_all(b".part");
+        let part_path = path.with_filename(part_name.as_slice());
+
+        let mut validator_name = part_name.clone();
+        validator_name.push_all(b".validator");
+        let validator_path = path.with_filename(validator_name.as_slice());
+
+        Download {
+            path: path.clone(),
+            part_path: part_path,
+            validator_path: validator_path,
+            max_attempts: 5,
+        }
+    }
+
+    /// Size of already downloaded part
+    pub fn downloaded(&self) -> u64 {
+        match fs::stat(&self.part_path) {
+            Ok(stat) => stat.size,
+            Err(_) => 0
+        }
+    }
+
+    /// Drops partial data, so the next `run` starts from scratch
+    pub fn reset(&self) {
+        let _ = fs::unlink(&self.part_path);
+        let _ = fs::unlink(&self.validator_path);
+    }
+
+    /// Performs GET `req` until the file is complete, other failure
+    /// occurs or `max_attempts` is reached. Response has no content,
+    /// it is in the file.
+    pub fn run(&self, client: &mut Client, mut req: Request) -> Result<Response, DownloadError> {
+        let mut attempts = 0;
+        let mut restarted = false;
+
+        loop {
+            attempts += 1;
+
+            let validator = File::open(&self.validator_path).read_to_string().ok();
+            let offset = match validator {
+                // Unvalidated data might belong to another version
+                Some(ref v) if v.len() > 0 => self.downloaded(),
+                _ => 0,
+            };
+
+            req.remove_header("If-Range");
+            req.remove_header("Range");
+            if offset > 0 {
+                req.set_header("If-Range", validator.as_ref().unwrap().as_slice());
+                req.resume_from = Some(offset);
+            } else {
+                req.resume_from = None;
+            }
+
+            let check = Arc::new(Mutex::new(HeadCheck { range_error: None }));
+            req.set_handler(box ResumeHandler {
+                part_path: self.part_path.clone(),
+                validator_path: self.validator_path.clone(),
+                offset: offset,
+                file: None,
+                check: check.clone(),
+            });
+
+            let res = client.perform(&mut req);
+
+            match check.lock().range_error.take() {
+                Some(msg) => {
+                    let err = CurlError::new(CURLE_RANGE_ERROR as uint, msg);
+                    if restarted {
+                        return Err(RangeError(err));
+                    }
+                    restarted = true;
+                    self.reset();
+                    continue;
+                },
+                None => ()
+            }
+
+            match res {
+                Ok(resp) => match resp.status_code {
+                    200 | 206 => return self.complete(resp),
+                    // Range starts at the end, i.e. file is complete already
+                    416 if offset > 0 && content_total(&resp.headers) == Some(offset) => {
+                        return self.complete(resp)
+                    },
+                    416 => {
+                        let err = CurlError::new(CURLE_BAD_DOWNLOAD_RESUME as uint,
+                                                 format!("Can't resume from {}", offset));
+                        if restarted || offset == 0 {
+                            return Err(BadResume(err));
+                        }
+                        restarted = true;
+                        self.reset();
+                    },
+                    status => return Err(HttpStatus(status)),
+                },
+                Err(e) => {
+                    let code = e.code as libc::c_uint;
+                    if code == CURLE_RANGE_ERROR || code == CURLE_BAD_DOWNLOAD_RESUME {
+                        // Resource has changed or server ignores ranges,
+                        // the only way out is to start over
+                        if restarted || offset == 0 {
+                            return Err(if code == CURLE_RANGE_ERROR { RangeError(e) } else { BadResume(e) });
+                        }
+                        restarted = true;
+                        self.reset();
+                    } else if is_resumable(code) && attempts < self.max_attempts {
+                        debug!("Download of {} interrupted at {}: {}",
+                               self.path.display(), self.downloaded(), e.message);
+                    } else {
+                        return Err(TransferFailed(e));
+                    }
+                }
+            }
+        }
+    }
+
+    fn complete(&self, resp: Response) -> Result<Response, DownloadError> {
+        // Body might be empty, so the part file is not there
+        if !self.part_path.exists() {
+            match File::create(&self.part_path) {
+                Ok(_) => (),
+                Err(e) => return Err(FileError(e)),
+            }
+        }
+        match fs::rename(&self.part_path, &self.path) {
+            Ok(()) => {
+                let _ = fs::unlink(&self.validator_path);
+                Ok(resp)
+            },
+            Err(e) => Err(FileError(e)),
+        }
+    }
+}
+
+fn is_resumable(code: libc::c_uint) -> bool {
+    code == CURLE_PARTIAL_FILE || code == CURLE_OPERATION_TIMEDOUT ||
+        code == CURLE_RECV_ERROR || code == CURLE_SEND_ERROR ||
+        code == CURLE_GOT_NOTHING || code == CURLE_COULDNT_CONNECT
+}
+
+// Content-Range: bytes 100-199/1000 -> (Some(100), Some(1000))
+// Content-Range: bytes */1000 -> (None, Some(1000))
+fn parse_content_range(value: &str) -> (Option<u64>, Option<u64>) {
+    let spec = match value.trim().split(' ').nth(1) {
+        Some(spec) => spec,
+        None => return (None, None),
+    };
+    let mut parts = spec.split('/');
+    let range = parts.next().unwrap_or("");
+    let total = parts.next().and_then(|t| from_str::<u64>(t));
+    let start = range.split('-').next().and_then(|s| from_str::<u64>(s));
+    (start, total)
+}
+
+fn content_total(headers: &Headers) -> Option<u64> {
+    headers.get("Content-Range").and_then(|v| parse_content_range(v).val1())
+}
+
+impl Handler for ResumeHandler {
+    fn head(&mut self, status: u16, headers: &Headers) -> IoResult<()> {
+        let mode = match status {
+            200 => {
+                // Full body, remember what it is to resume it later
+                let validator = headers.etag()
+                    .or_else(|| headers.get("Last-Modified"))
+                    .unwrap_or("");
+                try!(File::create(&self.validator_path).and_then(|mut f| f.write_str(validator)));
+                Truncate
+            },
+            206 => {
+                let start = headers.get("Content-Range").and_then(|v| parse_content_range(v).val0());
+                if start != Some(self.offset) {
+                    let msg = format!("Requested range from {}, got {}", self.offset, start);
+                    self.check.lock().range_error = Some(msg);
+                    return Err(IoError {
+                        kind: OtherIoError,
+                        desc: "unexpected Content-Range",
+                        detail: None
+                    });
+                }
+                Append
+            },
+            // Error pages don't go to the file
+            _ => return Ok(()),
+        };
+
+        self.file = Some(try!(File::open_mode(&self.part_path, mode, Write)));
+        Ok(())
+    }
+
+    fn write(&mut self, data: &[u8]) -> IoResult<()> {
+        match self.file {
+            Some(ref mut f) => f.write(data),
+            None => Ok(()),
+        }
+    }
+
+    fn finish(&mut self) -> IoResult<()> {
+        match self.file.take() {
+            Some(mut f) => f.fsync(),
+            None => Ok(()),
+        }
+    }
+
+    // Partial data is kept, that's the whole point
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::{Download, parse_content_range};
+    use http::Client;
+    use std::io::{File, TempDir};
+
+    #[test]
+    fn content_range() {
+        assert_eq!(parse_content_range("bytes 100-199/1000"), (Some(100), Some(1000)));
+        assert_eq!(parse_content_range("bytes 100-199/*"), (Some(100), None));
+        assert_eq!(parse_content_range("bytes */1000"), (None, Some(1000)));
+        assert_eq!(parse_content_range("garbage"), (None, None));
+    }
+
+    #[test]
+    fn part_paths() {
+        let d = Download::new(&Path::new("/tmp/file.bin"));
+        assert_eq!(d.part_path, Path::new("/tmp/file.bin.part"));
+        assert_eq!(d.validator_path, Path::new("/tmp/file.bin.part.validator"));
+    }
+
+    #[test]
+    fn resume() {
+        let dir = TempDir::new("curl-download").unwrap();
+        let path = dir.path().join("range.bin");
+        let d = Download::new(&path);
+
+        let mut c = Client::new("http://httpbin.org/");
+        // httpbin serves the same content for the same size
+        let req = c.new_get_request("range/1024");
+        let resp = d.run(&mut c, req).unwrap();
+        let etag = resp.headers.etag().unwrap().to_string();
+        let full = File::open(&path).read_to_end().unwrap();
+
+        // Pretend first half was downloaded before
+        File::create(&d.part_path).unwrap().write(full.slice_to(512)).unwrap();
+        File::create(&d.validator_path).unwrap().write_str(etag.as_slice()).unwrap();
+        let req = c.new_get_request("range/1024");
+        let resp = d.run(&mut c, req).unwrap();
+        assert_eq!(resp.status_code, 206);
+        assert_eq!(File::open(&path).read_to_end().unwrap(), full);
+    }
+}
@@ -0,0 +1,314 @@
+use flate;
+use std::ascii::StrAsciiExt;
+use std::{cmp, mem};
+use std::io::{IoResult, IoError, InvalidInput};
+
+use easy::Curl;
+use handlers::Handler;
+use opt;
+use super::Headers;
+
+/// Content coding of a response body
+#[deriving(Clone, Show, PartialEq)]
+pub enum Coding {
+    Identity,
+    Gzip,
+    Deflate,
+    Brotli,
+    Zstd,
+}
+
+/// Which content codings are asked from the server,
+/// the body is decoded before it reaches the handler
+#[deriving(Clone, Show, PartialEq)]
+pub enum AcceptEncoding {
+    /// No `Accept-Encoding`, body is passed as received
+    RawEncoding,
+    /// Everything the linked libcurl decodes
+    AllSupported,
+    /// Listed codings in order of preference, ones which
+    /// can't be decoded are not asked for
+    Codings(Vec<Coding>),
+}
+
+impl Coding {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Identity => "identity",
+            Gzip => "gzip",
+            Deflate => "deflate",
+            Brotli => "br",
+            Zstd => "zstd",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Coding> {
+        match name.trim().to_ascii_lower().as_slice() {
+            "identity" => Some(Identity),
+            "gzip" | "x-gzip" => Some(Gzip),
+            "deflate" => Some(Deflate),
+            "br" => Some(Brotli),
+            "zstd" => Some(Zstd),
+            _ => None
+        }
+    }
+
+    /// Whether the linked libcurl decodes it
+    pub fn is_supported(&self) -> bool {
+        let bit = match *self {
+            Identity => return true,
+            Gzip | Deflate => ::VERSION_LIBZ,
+            Brotli => ::VERSION_BROTLI,
+            Zstd => ::VERSION_ZSTD,
+        };
+        ::features() & bit != 0
+    }
+
+    /// Whether `decode` handles it, for libcurl built without zlib
+    pub fn is_decodable(&self) -> bool {
+        match *self {
+            Identity | Gzip | Deflate => true,
+            _ => false
+        }
+    }
+}
+
+impl AcceptEncoding {
+    // Body is decoded by `Decoder` rather than by libcurl
+    pub fn decodes_in_rust(&self) -> bool {
+        let has_zlib = Gzip.is_supported();
+        match *self {
+            RawEncoding => false,
+            AllSupported => !has_zlib,
+            Codings(ref codings) => !has_zlib && codings.iter().any(|c| *c == Gzip || *c == Deflate),
+        }
+    }
+
+    pub fn apply(&self, session: &Curl) {
+        let rust = self.decodes_in_rust();
+        let value = match *self {
+            RawEncoding => None,
+            AllSupported if !rust => Some(String::new()),
+            AllSupported => Some("gzip, deflate".to_string()),
+            Codings(ref codings) => {
+                let names: Vec<&str> = codings.iter()
+                    .filter(|c| if rust { c.is_decodable() } else { c.is_supported() })
+                    .map(|c| c.name())
+                    .collect();
+                Some(if names.is_empty() { "identity".to_string() } else { names.connect(", ") })
+            }
+        };
+
+        match value {
+            // Empty string makes libcurl list what it supports
+            Some(ref value) => session.setopt(opt::ACCEPT_ENCODING, value.as_slice()),
+            None => session.setopt(opt::ACCEPT_ENCODING, 0u),
+        };
+        session.setopt(opt::HTTP_CONTENT_DECODING, !rust);
+    }
+}
+
+fn corrupt(desc: &'static str) -> IoError {
+    IoError {
+        kind: InvalidInput,
+        desc: desc,
+        detail: None,
+    }
+}
+
+// Skips gzip header of RFC 1952, returns offset of deflate data
+fn gzip_header_len(data: &[u8]) -> IoResult<uint> {
+    static FHCRC: u8 = 1 << 1;
+    static FEXTRA: u8 = 1 << 2;
+    static FNAME: u8 = 1 << 3;
+    static FCOMMENT: u8 = 1 << 4;
+
+    let err = corrupt("invalid gzip header");
+    if data.len() < 18 || data[0] != 0x1f || data[1] != 0x8b || data[2] != 8 {
+        return Err(err);
+    }
+    let flags = data[3];
+    let mut pos = 10;
+    if flags & FEXTRA != 0 {
+        if pos + 2 > data.len() {
+            return Err(err);
+        }
+        pos += 2 + (data[pos] as uint | (data[pos + 1] as uint << 8));
+    }
+    for &flag in [FNAME, FCOMMENT].iter() {
+        if flags & flag != 0 {
+            match data.slice_from(cmp::min(pos, data.len())).iter().position(|&b| b == 0) {
+                Some(n) => pos += n + 1,
+                None => return Err(err)
+            }
+        }
+    }
+    if flags & FHCRC != 0 {
+        pos += 2;
+    }
+    if pos + 8 > data.len() {
+        return Err(err);
+    }
+    Ok(pos)
+}
+
+/// Decodes the whole body, only codings which are `is_decodable`
+pub fn decode(coding: Coding, data: &[u8]) -> IoResult<Vec<u8>> {
+    match coding {
+        Identity => Ok(data.to_vec()),
+        Gzip => {
+            let start = try!(gzip_header_len(data));
+            let (body, trailer) = (data.slice(start, data.len() - 8), data.slice_from(data.len() - 8));
+            let decoded = try!(flate::inflate_bytes(body).ok_or(corrupt("invalid gzip data")));
+
+            // ISIZE is the length modulo 2^32, CRC is not checked
+            let size = trailer.slice_from(4).iter().rev().fold(0u32, |acc, &b| (acc << 8) | b as u32);
+            if size != decoded.len() as u32 {
+                return Err(corrupt("gzip size mismatch"));
+            }
+            Ok(decoded.as_slice().to_vec())
+        },
+        // Should be zlib format, but raw deflate is seen in the wild
+        Deflate => flate::inflate_bytes_zlib(data).or_else(|| flate::inflate_bytes(data))
+            .map(|d| d.as_slice().to_vec())
+            .ok_or(corrupt("invalid deflate data")),
+        _ => Err(corrupt("unsupported content coding")),
+    }
+}
+
+/// Codings of `Content-Encoding` in order they were applied,
+/// unknown coding name is an error
+pub fn content_codings(headers: &Headers) -> Result<Vec<Coding>, String> {
+    let value = match headers.get("Content-Encoding") {
+        Some(v) => v,
+        None => return Ok(Vec::new())
+    };
+    let mut codings = Vec::new();
+    for name in value.split(',').filter(|s| !s.trim().is_empty()) {
+        match Coding::parse(name) {
+            Some(coding) => codings.push(coding),
+            None => return Err(name.trim().to_string())
+        }
+    }
+    Ok(codings)
+}
+
+/// Handler which collects compressed body and passes
+/// the decoded one to `inner` once it is complete
+pub struct Decoder {
+    inner: Box<Handler+Send>,
+    codings: Vec<Coding>,
+    buf: Vec<u8>,
+}
+
+impl Decoder {
+    pub fn new(inner: Box<Handler+Send>) -> Decoder {
+        Decoder {
+            inner: inner,
+            codings: Vec::new(),
+            buf: Vec::new(),
+        }
+    }
+}
+
+impl Handler for Decoder {
+    fn head(&mut self, status: u16, headers: &Headers) -> IoResult<()> {
+        self.codings = match content_codings(headers) {
+            Ok(codings) => codings.into_iter().filter(|c| *c != Identity).collect(),
+            Err(name) => return Err(IoError {
+                kind: InvalidInput,
+                desc: "unknown content coding",
+                detail: Some(name),
+            })
+        };
+        if self.codings.iter().any(|c| !c.is_decodable()) {
+            return Err(corrupt("unsupported content coding"));
+        }
+        self.inner.head(status, headers)
+    }
+
+    fn write(&mut self, data: &[u8]) -> IoResult<()> {
+        if self.codings.is_empty() {
+            self.inner.write(data)
+        } else {
+            self.buf.push_all(data);
+            Ok(())
+        }
+    }
+
+    fn finish(&mut self) -> IoResult<()> {
+        if !self.codings.is_empty() && !self.buf.is_empty() {
+            // Codings are undone in reverse order
+            let mut data = mem::replace(&mut self.buf, Vec::new());
+            for &coding in self.codings.iter().rev() {
+                data = try!(decode(coding, data.as_slice()));
+            }
+            try!(self.inner.write(data.as_slice()));
+        }
+        self.inner.finish()
+    }
+
+    fn abort(&mut self) {
+        self.inner.abort()
+    }
+
+    fn into_reader(self: Box<Decoder>) -> Option<Box<Reader+'static>> {
+        let Decoder { inner, .. } = *self;
+        inner.into_reader()
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::{decode, Decoder, Coding, Gzip, Deflate, Brotli, Identity, AllSupported};
+    use handlers::{Handler, MemoryHandler};
+    use http::{Client, Headers};
+
+    // "hello hello hello" compressed by gzip(1) with a file name
+    static GZIPPED: &'static [u8] = &[
+        0x1f, 0x8b, 0x08, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x61, 0x00,
+        0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x90, 0x00, 0x80, 0x88,
+        0xf9, 0xe5, 0x11, 0x00, 0x00, 0x00];
+
+    #[test]
+    fn gzip() {
+        assert_eq!(decode(Gzip, GZIPPED).unwrap(), b"hello hello hello".to_vec());
+        assert!(decode(Gzip, GZIPPED.slice_to(20)).is_err());
+        assert!(decode(Brotli, GZIPPED).is_err());
+    }
+
+    #[test]
+    fn codings() {
+        assert_eq!(Coding::parse(" X-GZIP"), Some(Gzip));
+        assert_eq!(Coding::parse("compress"), None);
+        assert!(Identity.is_supported());
+        assert!(Deflate.is_decodable() && !Brotli.is_decodable());
+    }
+
+    #[test]
+    fn decoder() {
+        let mut h = Headers::new();
+        h.append("Content-Encoding", "gzip");
+        let mut d = box Decoder::new(box MemoryHandler::new());
+        d.head(200, &h).unwrap();
+        d.write(GZIPPED.slice_to(10)).unwrap();
+        d.write(GZIPPED.slice_from(10)).unwrap();
+        d.finish().unwrap();
+        assert_eq!(d.into_reader().unwrap().read_to_end().unwrap(), b"hello hello hello".to_vec());
+    }
+
+    #[test]
+    fn gzip_response() {
+        let mut c = Client::new("http://httpbin.org/");
+        c.set_accept_encoding(AllSupported);
+        let mut req = c.new_get_request("gzip");
+
+        let resp = c.perform(&mut req).unwrap();
+        assert_eq!(resp.content_encoding, Some("gzip".to_string()));
+        let size = resp.compressed_size.unwrap();
+        let content = resp.content_data.unwrap().read_to_end().unwrap();
+        assert!(content.as_slice().starts_with(b"{"));
+        assert!(size > 0 && size < content.len() as u64);
+    }
+}
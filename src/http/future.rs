@@ -0,0 +1,374 @@
+use libc::uintptr_t;
+use std::collections::HashMap;
+use std::comm::{Empty, Disconnected};
+use std::io::MemReader;
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use easy::Curl;
+use epoll::{EpollDriver, Waker};
+use errors::{CURLE_FAILED_INIT, CURLE_WRITE_ERROR, CURLE_ABORTED_BY_CALLBACK};
+use opt;
+use super::{Client, CurlError, Method, Get, Post, Put, Patch, Request, Response, ResponseHead,
+            Headers, Version, Scheme, RetryAttempt, Redirect, CacheStatus, ConnectionInfo,
+            Cookie, CookieJar};
+use super::transfer::Transfer;
+
+/// Longest wait of the loop, commands wake it earlier
+static TURN_TIMEOUT_MS: uint = 1000;
+
+// Response is not Send because of `content_data`, so its fields
+// are passed with the body read into memory on the loop thread
+struct Delivery {
+    url: String,
+    headers: Headers,
+    status_code: u16,
+    status_message: String,
+    version: Option<Version>,
+    interim: Vec<ResponseHead>,
+    trailers: Headers,
+    auth_offered: Vec<Scheme>,
+    attempts: Vec<RetryAttempt>,
+    history: Vec<Redirect>,
+    content_encoding: Option<String>,
+    compressed_size: Option<u64>,
+    cache_status: Option<CacheStatus>,
+    connection: ConnectionInfo,
+    body: Option<Vec<u8>>,
+}
+
+impl Delivery {
+    fn new(mut response: Response) -> Result<Delivery, CurlError> {
+        let body = match response.content_data.take() {
+            Some(mut reader) => match reader.read_to_end() {
+                Ok(body) => Some(body),
+                Err(e) => return Err(CurlError::new(CURLE_WRITE_ERROR as uint, e.to_string()))
+            },
+            None => None
+        };
+        Ok(Delivery {
+            url: response.url,
+            headers: response.headers,
+            status_code: response.status_code,
+            status_message: response.status_message,
+            version: response.version,
+            interim: response.interim,
+            trailers: response.trailers,
+            auth_offered: response.auth_offered,
+            attempts: response.attempts,
+            history: response.history,
+            content_encoding: response.content_encoding,
+            compressed_size: response.compressed_size,
+            cache_status: response.cache_status,
+            connection: response.connection,
+            body: body,
+        })
+    }
+
+    fn take(self) -> Response {
+        Response {
+            url: self.url,
+            headers: self.headers,
+            status_code: self.status_code,
+            status_message: self.status_message,
+            version: self.version,
+            interim: self.interim,
+            trailers: self.trailers,
+            auth_offered: self.auth_offered,
+            attempts: self.attempts,
+            history: self.history,
+            content_encoding: self.content_encoding,
+            compressed_size: self.compressed_size,
+            cache_status: self.cache_status,
+            connection: self.connection,
+            content_data: self.body.map(|body| box MemReader::new(body) as Box<Reader+'static>),
+        }
+    }
+}
+
+// Cookies of the copy go back to the client with the result
+type Reply = (Result<Delivery, CurlError>, Vec<Cookie>);
+
+enum Command {
+    Start(uint, Curl, Request, Sender<Reply>),
+    Cancel(uint),
+}
+
+struct Inner {
+    client: Client,
+    commands: Sender<Command>,
+    next_id: uint,
+}
+
+/// `Client` whose requests are performed by a background thread,
+/// `perform` returns at once with a future of the response
+///
+/// The thread runs transfers through the socket interface of a
+/// multi handle, see `EpollDriver`. Like `Client::perform_all`,
+/// interceptors, cache, retry policy and credential provider are
+/// not used, the body is buffered in memory. Cookies of a response
+/// go to the client once its future returns it. The thread exits
+/// once the client and its futures are dropped.
+pub struct AsyncClient {
+    inner: Arc<Mutex<Inner>>,
+    waker: Waker,
+}
+
+/// Response of `AsyncClient::perform`, dropping it before
+/// completion aborts the transfer
+pub struct ResponseFuture {
+    id: uint,
+    reply: Receiver<Reply>,
+    done: bool,
+    // Receives cookies of the response
+    inner: Arc<Mutex<Inner>>,
+    commands: Sender<Command>,
+    waker: Waker,
+}
+
+fn shut_down() -> CurlError {
+    CurlError::new(CURLE_ABORTED_BY_CALLBACK as uint, "Client is dropped".to_string())
+}
+
+impl AsyncClient {
+    /// Requests are constructed and performed with copies
+    /// of the `client` session
+    pub fn new(client: Client) -> Result<AsyncClient, CurlError> {
+        let driver = match EpollDriver::new() {
+            Ok(driver) => driver,
+            Err(e) => return Err(CurlError::new(CURLE_FAILED_INIT as uint, e.to_string()))
+        };
+        let waker = driver.waker();
+        let (tx, rx) = channel();
+        spawn(proc() { run(driver, rx) });
+
+        Ok(AsyncClient {
+            inner: Arc::new(Mutex::new(Inner {
+                client: client,
+                commands: tx,
+                next_id: 0,
+            })),
+            waker: waker,
+        })
+    }
+
+    pub fn new_get_request(&self, rel_url: &str) -> Request {
+        self.new_request(rel_url, Get)
+    }
+
+    pub fn new_post_request(&self, rel_url: &str) -> Request {
+        self.new_request(rel_url, Post)
+    }
+
+    pub fn new_put_request(&self, rel_url: &str) -> Request {
+        self.new_request(rel_url, Put)
+    }
+
+    pub fn new_patch_request(&self, rel_url: &str) -> Request {
+        self.new_request(rel_url, Patch)
+    }
+
+    fn new_request(&self, rel_url: &str, method: Method) -> Request {
+        self.inner.lock().client.new_request(rel_url, method)
+    }
+
+    /// Starts the transfer in the background
+    pub fn perform(&self, req: Request) -> ResponseFuture {
+        let (tx, rx) = channel();
+        let mut inner = self.inner.lock();
+        let id = inner.next_id;
+        inner.next_id += 1;
+
+        let curl = inner.client.session.duphandle();
+        // Client writes the jar file, not the copy
+        curl.setopt(opt::COOKIEJAR, 0u);
+        // Dead loop drops the reply sender, so the future sees an error
+        let _ = inner.commands.send_opt(Start(id, curl, req, tx));
+        self.waker.wake();
+
+        ResponseFuture {
+            id: id,
+            reply: rx,
+            done: false,
+            inner: self.inner.clone(),
+            commands: inner.commands.clone(),
+            waker: self.waker.clone(),
+        }
+    }
+}
+
+impl ResponseFuture {
+    /// Blocks until the transfer completes
+    pub fn get(mut self) -> Result<Response, CurlError> {
+        self.done = true;
+        match self.reply.recv_opt() {
+            Ok(reply) => self.receive(reply),
+            Err(()) => Err(shut_down())
+        }
+    }
+
+    /// Result if the transfer has completed, doesn't block
+    pub fn try_get(&mut self) -> Option<Result<Response, CurlError>> {
+        if self.done {
+            return None;
+        }
+        match self.reply.try_recv() {
+            Ok(reply) => {
+                self.done = true;
+                Some(self.receive(reply))
+            },
+            Err(Empty) => None,
+            Err(Disconnected) => {
+                self.done = true;
+                Some(Err(shut_down()))
+            }
+        }
+    }
+
+    fn receive(&self, (result, cookies): Reply) -> Result<Response, CurlError> {
+        let inner = self.inner.lock();
+        let jar = CookieJar::new(&inner.client.session);
+        for cookie in cookies.iter() {
+            let _ = jar.add(cookie);
+        }
+        result.map(|d| d.take())
+    }
+}
+
+impl Drop for ResponseFuture {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = self.commands.send_opt(Cancel(self.id));
+            self.waker.wake();
+        }
+    }
+}
+
+impl Drop for AsyncClient {
+    fn drop(&mut self) {
+        // Closes the client end of the channel right away
+        let (tx, _) = channel();
+        drop(mem::replace(&mut self.inner.lock().commands, tx));
+        self.waker.wake();
+    }
+}
+
+// Background loop, runs until every sender of commands is dropped
+fn run(mut driver: EpollDriver, commands: Receiver<Command>) {
+    let (done_tx, done_rx) = channel();
+    let mut transfers: HashMap<uintptr_t, (uint, Transfer, Sender<Reply>)> = HashMap::new();
+    let mut handles: HashMap<uint, uintptr_t> = HashMap::new();
+
+    'main: loop {
+        loop {
+            match commands.try_recv() {
+                Ok(Start(id, curl, req, reply)) => {
+                    let transfer = match Transfer::new(&curl, req) {
+                        Ok(transfer) => transfer,
+                        Err(e) => {
+                            let _ = reply.send_opt((Err(e), Vec::new()));
+                            continue;
+                        }
+                    };
+                    let handle = curl.raw_handle();
+                    let done_tx = done_tx.clone();
+                    match driver.add(curl, proc(curl, code) { done_tx.send((curl, code)) }) {
+                        Ok(()) => {
+                            transfers.insert(handle, (id, transfer, reply));
+                            handles.insert(id, handle);
+                        },
+                        Err(e) => {
+                            let _ = reply.send_opt((Err(CurlError::new(CURLE_FAILED_INIT as uint, e.message)), Vec::new()));
+                        }
+                    }
+                },
+                Ok(Cancel(id)) => {
+                    match handles.pop(&id) {
+                        Some(handle) => {
+                            // Handle goes before the data it points to
+                            drop(driver.remove(handle));
+                            transfers.remove(&handle);
+                        },
+                        None => ()
+                    }
+                },
+                Err(Empty) => break,
+                Err(Disconnected) => break 'main,
+            }
+        }
+
+        match driver.turn(TURN_TIMEOUT_MS) {
+            Ok(_) => (),
+            Err(e) => debug!("Multi loop failed: {}", e.message)
+        }
+
+        loop {
+            let (curl, code) = match done_rx.try_recv() {
+                Ok(done) => done,
+                Err(_) => break
+            };
+            match transfers.pop(&curl.raw_handle()) {
+                Some((id, transfer, reply)) => {
+                    handles.remove(&id);
+                    let result = transfer.complete(&curl, code).and_then(|resp| Delivery::new(resp));
+                    let cookies = CookieJar::new(&curl).list();
+                    let _ = reply.send_opt((result, cookies));
+                },
+                None => ()
+            }
+        }
+    }
+
+    // Aborts what is left before the data it points to is freed
+    drop(driver);
+}
+
+#[cfg(test)]
+mod test
+{
+    use std::io::timer;
+    use std::time::Duration;
+    use super::AsyncClient;
+    use http::Client;
+
+    #[test]
+    fn futures() {
+        let c = AsyncClient::new(Client::new("http://httpbin.org/")).unwrap();
+        let slow = c.perform(c.new_get_request("delay/1"));
+        let fast = c.perform(c.new_get_request("get?n=1"));
+
+        let resp = fast.get().unwrap();
+        assert_eq!(resp.status_code, 200);
+        let content = resp.content_data.unwrap().read_to_string().unwrap();
+        assert!(content.as_slice().contains("\"n\": \"1\""));
+
+        assert_eq!(slow.get().unwrap().status_code, 200);
+
+        let mut failed = c.perform(c.new_get_request("http://nonexistent.invalid/"));
+        loop {
+            match failed.try_get() {
+                Some(result) => {
+                    assert!(result.is_err());
+                    break;
+                },
+                None => timer::sleep(Duration::milliseconds(10))
+            }
+        }
+    }
+
+    #[test]
+    fn cancel() {
+        let c = AsyncClient::new(Client::new("http://httpbin.org/")).unwrap();
+        drop(c.perform(c.new_get_request("delay/10")));
+        // Cancelled transfer doesn't hold the loop up
+        assert_eq!(c.perform(c.new_get_request("get")).get().unwrap().status_code, 200);
+    }
+
+    #[test]
+    fn cookies_reach_client() {
+        let c = AsyncClient::new(Client::new("http://httpbin.org/")).unwrap();
+        assert!(c.perform(c.new_get_request("cookies/set?async=1")).get().is_ok());
+        let mut inner = c.inner.lock();
+        assert!(inner.client.cookie_jar().list().iter().any(|cookie| cookie.name.as_slice() == "async"));
+    }
+}
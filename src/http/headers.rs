@@ -0,0 +1,263 @@
+use std::ascii::StrAsciiExt;
+use std::slice;
+use time;
+use time::Tm;
+
+/// HTTP header map
+///
+/// Keeps headers in the order they were received, including
+/// repeated ones like `Set-Cookie`, names are matched
+/// case-insensitively as HTTP/2 sends them lowercase.
+#[deriving(Clone, Show, PartialEq)]
+pub struct Headers {
+    entries: Vec<(String, String)>,
+}
+
+/// Parsed `Cache-Control` header
+#[deriving(Clone, Show, PartialEq)]
+pub struct CacheControl {
+    pub no_cache: bool,
+    pub no_store: bool,
+    pub no_transform: bool,
+    pub must_revalidate: bool,
+    pub proxy_revalidate: bool,
+    pub public: bool,
+    pub private: bool,
+    pub immutable: bool,
+    pub max_age: Option<u64>,
+    pub s_maxage: Option<u64>,
+    pub stale_while_revalidate: Option<u64>,
+    pub stale_if_error: Option<u64>,
+}
+
+impl Headers {
+    pub fn new() -> Headers {
+        Headers {
+            entries: Vec::new()
+        }
+    }
+
+    pub fn len(&self) -> uint {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear()
+    }
+
+    /// Adds a header, existing ones with the same name are kept.
+    /// Whitespace around name and value is not a part of them.
+    pub fn append(&mut self, name: &str, value: &str) {
+        self.entries.push((name.trim().to_string(), value.trim().to_string()));
+    }
+
+    /// Replaces all headers with this name
+    pub fn set(&mut self, name: &str, value: &str) {
+        self.remove(name);
+        self.append(name, value);
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.entries.retain(|&(ref k, _)| !k.as_slice().eq_ignore_ascii_case(name));
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// First value of the header
+    pub fn get<'a>(&'a self, name: &str) -> Option<&'a str> {
+        self.entries.iter()
+            .find(|&&(ref k, _)| k.as_slice().eq_ignore_ascii_case(name))
+            .map(|&(_, ref v)| v.as_slice())
+    }
+
+    /// All values of the header in the received order
+    pub fn get_all<'a>(&'a self, name: &str) -> Vec<&'a str> {
+        self.entries.iter()
+            .filter(|&&(ref k, _)| k.as_slice().eq_ignore_ascii_case(name))
+            .map(|&(_, ref v)| v.as_slice())
+            .collect()
+    }
+
+    /// All values of a list-based header like `Via`,
+    /// split by commas (which is only valid for such headers)
+    pub fn get_list<'a>(&'a self, name: &str) -> Vec<&'a str> {
+        let mut res = Vec::new();
+        for v in self.get_all(name).into_iter() {
+            res.extend(v.split(',').map(|s| s.trim()).filter(|s| s.len() > 0));
+        }
+        res
+    }
+
+    /// (name, value) pairs in the received order
+    pub fn iter<'a>(&'a self) -> slice::Items<'a, (String, String)> {
+        self.entries.iter()
+    }
+
+    pub fn content_length(&self) -> Option<u64> {
+        self.get("Content-Length").and_then(|v| from_str(v))
+    }
+
+    /// Full Content-Type, including parameters
+    pub fn content_type<'a>(&'a self) -> Option<&'a str> {
+        self.get("Content-Type")
+    }
+
+    /// Content-Type without parameters, lowercase
+    pub fn mime_type(&self) -> Option<String> {
+        self.content_type().map(|ct| ct.split(';').next().unwrap().trim().to_ascii_lower())
+    }
+
+    /// `charset` parameter of Content-Type
+    pub fn charset(&self) -> Option<String> {
+        self.content_type().and_then(|ct| {
+            ct.split(';').skip(1)
+                .map(|p| p.trim())
+                .find(|p| p.len() > 8 && p.slice_to(8).eq_ignore_ascii_case("charset="))
+                .map(|p| p.slice_from(8).trim_chars('"').to_string())
+        })
+    }
+
+    pub fn date(&self) -> Option<Tm> {
+        self.get("Date").and_then(parse_http_date)
+    }
+
+    pub fn last_modified(&self) -> Option<Tm> {
+        self.get("Last-Modified").and_then(parse_http_date)
+    }
+
+    pub fn expires(&self) -> Option<Tm> {
+        self.get("Expires").and_then(parse_http_date)
+    }
+
+    pub fn location<'a>(&'a self) -> Option<&'a str> {
+        self.get("Location")
+    }
+
+    pub fn etag<'a>(&'a self) -> Option<&'a str> {
+        self.get("ETag")
+    }
+
+    /// Directives of all Cache-Control headers
+    pub fn cache_control(&self) -> Option<CacheControl> {
+        let directives = self.get_list("Cache-Control");
+        if directives.is_empty() {
+            None
+        } else {
+            Some(CacheControl::parse(directives.as_slice()))
+        }
+    }
+}
+
+impl CacheControl {
+    fn parse(directives: &[&str]) -> CacheControl {
+        let mut cc = CacheControl {
+            no_cache: false,
+            no_store: false,
+            no_transform: false,
+            must_revalidate: false,
+            proxy_revalidate: false,
+            public: false,
+            private: false,
+            immutable: false,
+            max_age: None,
+            s_maxage: None,
+            stale_while_revalidate: None,
+            stale_if_error: None,
+        };
+
+        for d in directives.iter() {
+            let mut parts = d.splitn('=', 1);
+            let name = parts.next().unwrap().trim().to_ascii_lower();
+            let value = parts.next().map(|v| v.trim().trim_chars('"'));
+            let seconds = value.and_then(|v| from_str::<u64>(v));
+
+            match name.as_slice() {
+                "no-cache" => cc.no_cache = true,
+                "no-store" => cc.no_store = true,
+                "no-transform" => cc.no_transform = true,
+                "must-revalidate" => cc.must_revalidate = true,
+                "proxy-revalidate" => cc.proxy_revalidate = true,
+                "public" => cc.public = true,
+                "private" => cc.private = true,
+                "immutable" => cc.immutable = true,
+                "max-age" => cc.max_age = seconds,
+                "s-maxage" => cc.s_maxage = seconds,
+                "stale-while-revalidate" => cc.stale_while_revalidate = seconds,
+                "stale-if-error" => cc.stale_if_error = seconds,
+                _ => debug!("Unknown Cache-Control directive: {}", d)
+            }
+        }
+        cc
+    }
+}
+
+/// Parses any of the three date formats allowed by RFC 7231:
+/// IMF-fixdate, obsolete RFC 850 and asctime
+pub fn parse_http_date(s: &str) -> Option<Tm> {
+    let formats = ["%a, %d %b %Y %H:%M:%S GMT", "%A, %d-%b-%y %H:%M:%S GMT", "%a %b %e %H:%M:%S %Y"];
+    formats.iter()
+        .filter_map(|f| time::strptime(s.trim(), *f).ok())
+        .next()
+}
+
+/// Formats date as IMF-fixdate, i.e. `Sun, 06 Nov 1994 08:49:37 GMT`
+pub fn format_http_date(tm: &Tm) -> String {
+    tm.to_utc().strftime("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::{Headers, parse_http_date};
+
+    #[test]
+    fn multi_valued() {
+        let mut h = Headers::new();
+        h.append("Set-Cookie", "a=1");
+        h.append("content-type", "text/html; charset=\"UTF-8\"");
+        h.append("set-cookie", " b=2\r\n");
+
+        assert_eq!(h.get("SET-COOKIE"), Some("a=1"));
+        assert_eq!(h.get_all("Set-Cookie"), vec!("a=1", "b=2"));
+        assert_eq!(h.content_type(), Some("text/html; charset=\"UTF-8\""));
+        assert_eq!(h.mime_type(), Some("text/html".to_string()));
+        assert_eq!(h.charset(), Some("UTF-8".to_string()));
+
+        h.set("Set-Cookie", "c=3");
+        assert_eq!(h.get_all("set-cookie"), vec!("c=3"));
+        assert_eq!(h.iter().map(|&(ref k, _)| k.clone()).collect::<Vec<String>>(),
+                   vec!("content-type".to_string(), "Set-Cookie".to_string()));
+    }
+
+    #[test]
+    fn typed() {
+        let mut h = Headers::new();
+        h.append("Content-Length", "42");
+        h.append("Cache-Control", "public, max-age=60");
+        h.append("Cache-Control", "must-revalidate");
+        h.append("Via", "1.1 a, 1.1 b");
+        h.append("Via", "2 c");
+
+        assert_eq!(h.content_length(), Some(42));
+        let cc = h.cache_control().unwrap();
+        assert!(cc.public && cc.must_revalidate && !cc.no_store);
+        assert_eq!(cc.max_age, Some(60));
+        assert_eq!(h.get_list("Via"), vec!("1.1 a", "1.1 b", "2 c"));
+    }
+
+    #[test]
+    fn dates() {
+        let a = parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT").unwrap();
+        let b = parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT").unwrap();
+        let c = parse_http_date("Sun Nov  6 08:49:37 1994").unwrap();
+        assert_eq!(a.to_timespec(), b.to_timespec());
+        assert_eq!(a.to_timespec(), c.to_timespec());
+        assert!(parse_http_date("yesterday").is_none());
+    }
+}
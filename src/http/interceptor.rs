@@ -0,0 +1,171 @@
+use super::{CurlError, Request, Response};
+
+/// Hooks around `Client::perform`
+///
+/// `before_request` hooks run in the order interceptors were added,
+/// the others run in the reverse order, so the first interceptor
+/// sees the request first and the response last.
+///
+/// The chain runs once per `perform`, around all redirects,
+/// retries and authentication rounds it makes.
+pub trait Interceptor {
+    /// May change the request, e.g. add headers or sign it.
+    /// Changes of URL, headers and method apply to this call only,
+    /// the caller gets its request back as it was.
+    /// Returned response is used instead of performing the request,
+    /// the rest of interceptors don't see the request then.
+    #[allow(unused_variable)]
+    fn before_request(&mut self, req: &mut Request) -> Option<Response> {
+        None
+    }
+
+    /// May change the response or turn it into an error
+    #[allow(unused_variable)]
+    fn after_response(&mut self, req: &Request, resp: &mut Response) -> Result<(), CurlError> {
+        Ok(())
+    }
+
+    #[allow(unused_variable)]
+    fn on_error(&mut self, req: &Request, err: &mut CurlError) {
+    }
+}
+
+// Runs interceptors around `perform`
+pub fn intercept(interceptors: &mut Vec<Box<Interceptor+Send>>, req: &mut Request,
+                 perform: |&mut Request| -> Result<Response, CurlError>) -> Result<Response, CurlError> {
+    // Restored once the chain is over, so repeated calls start afresh
+    let url = req.url.clone();
+    let headers = req.headers.clone();
+    let method = req.method;
+
+    let mut passed = 0;
+    let mut short_circuit = None;
+    for i in interceptors.iter_mut() {
+        match i.before_request(req) {
+            Some(resp) => {
+                short_circuit = Some(resp);
+                break;
+            },
+            None => passed += 1
+        }
+    }
+
+    let mut res = match short_circuit {
+        Some(mut resp) => {
+            if resp.url.is_empty() {
+                resp.url = req.url().to_string();
+            }
+            Ok(resp)
+        },
+        None => perform(req)
+    };
+
+    for idx in range(0, passed).rev() {
+        let i = interceptors.get_mut(idx);
+        res = match res {
+            Ok(mut resp) => i.after_response(req, &mut resp).map(|_| resp),
+            Err(mut err) => {
+                i.on_error(req, &mut err);
+                Err(err)
+            }
+        };
+    }
+
+    req.url = url;
+    req.headers = headers;
+    req.method = method;
+    res
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::Interceptor;
+    use errors::CURLE_HTTP_RETURNED_ERROR;
+    use http::{Client, CurlError, Headers, Request, Response};
+    use std::sync::{Arc, Mutex};
+
+    // Records hook calls
+    struct Log {
+        name: &'static str,
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Interceptor for Log {
+        fn before_request(&mut self, req: &mut Request) -> Option<Response> {
+            self.log.lock().push(format!("before {}", self.name));
+            req.set_header("X-Trace", self.name);
+            None
+        }
+
+        fn after_response(&mut self, _: &Request, _: &mut Response) -> Result<(), CurlError> {
+            self.log.lock().push(format!("after {}", self.name));
+            Ok(())
+        }
+
+        fn on_error(&mut self, _: &Request, _: &mut CurlError) {
+            self.log.lock().push(format!("error {}", self.name));
+        }
+    }
+
+    struct Stub;
+
+    impl Interceptor for Stub {
+        fn before_request(&mut self, _: &mut Request) -> Option<Response> {
+            let mut headers = Headers::new();
+            headers.append("Content-Type", "text/plain");
+            Some(Response::synthetic(418, headers, b"teapot".to_vec()))
+        }
+    }
+
+    struct Reject;
+
+    impl Interceptor for Reject {
+        fn after_response(&mut self, _: &Request, resp: &mut Response) -> Result<(), CurlError> {
+            Err(CurlError::new(CURLE_HTTP_RETURNED_ERROR as uint, format!("status {}", resp.status_code)))
+        }
+    }
+
+    #[test]
+    fn short_circuit() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut c = Client::new("http://example.invalid/");
+        c.add_interceptor(box Log { name: "a", log: log.clone() });
+        c.add_interceptor(box Stub);
+        c.add_interceptor(box Log { name: "b", log: log.clone() });
+
+        let mut req = c.new_get_request("x");
+        let mut resp = c.perform(&mut req).unwrap();
+        assert_eq!(resp.status_code, 418);
+        assert_eq!(resp.url.as_slice(), "http://example.invalid/x");
+        assert_eq!(resp.content_data.as_mut().unwrap().read_to_end().unwrap(), b"teapot".to_vec());
+        assert_eq!(*log.lock(), vec!("before a".to_string(), "after a".to_string()));
+    }
+
+    #[test]
+    fn validation() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut c = Client::new("http://example.invalid/");
+        c.add_interceptor(box Log { name: "a", log: log.clone() });
+        c.add_interceptor(box Reject);
+        c.add_interceptor(box Stub);
+
+        let mut req = c.new_get_request("x");
+        assert!(c.perform(&mut req).is_err());
+        assert_eq!(*log.lock(), vec!("before a".to_string(), "error a".to_string()));
+    }
+
+    #[test]
+    fn request_is_restored() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut c = Client::new("http://example.invalid/");
+        c.add_interceptor(box Log { name: "a", log: log.clone() });
+        c.add_interceptor(box Stub);
+
+        let mut req = c.new_get_request("x");
+        c.perform(&mut req).unwrap();
+        c.perform(&mut req).unwrap();
+        assert!(req.headers.is_empty());
+        assert_eq!(log.lock().len(), 4);
+    }
+}
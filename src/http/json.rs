@@ -0,0 +1,174 @@
+use serialize::{json, Decodable, Encodable};
+use std::ascii::StrAsciiExt;
+use std::io::IoError;
+
+use super::{Body, Bytes, Headers};
+
+/// That many characters of the body are kept in `JsonError`
+static EXCERPT_LEN: uint = 200;
+
+#[deriving(Show)]
+pub enum JsonErrorKind {
+    /// Response has no body to decode
+    MissingBody,
+    BodyReadError(IoError),
+    /// Content type is not JSON, `None` if there's no `Content-Type`
+    WrongContentType(Option<String>),
+    UnsupportedCharset(String),
+    /// Body is not valid in the declared charset
+    MalformedText(String),
+    InvalidJson(json::DecoderError),
+}
+
+/// Error of `Response::json`
+#[deriving(Show)]
+pub struct JsonError {
+    pub kind: JsonErrorKind,
+    pub status: u16,
+    /// Beginning of the body, decoded lossily
+    pub excerpt: String,
+}
+
+/// Serialises `value` into a body
+pub fn to_body<'a, T: Encodable<json::Encoder<'a>, IoError>>(value: &T) -> Body {
+    Bytes(json::encode(value).into_bytes())
+}
+
+/// Whether media type is JSON, i.e. `application/json` or `*/*+json`
+pub fn is_json_type(mime_type: &str) -> bool {
+    let mime_type = mime_type.to_ascii_lower();
+    mime_type.as_slice() == "application/json" || mime_type.as_slice().ends_with("+json")
+}
+
+fn excerpt(body: &[u8]) -> String {
+    let text = String::from_utf8_lossy(body).into_string();
+    if text.as_slice().char_len() <= EXCERPT_LEN {
+        return text;
+    }
+    let mut short: String = text.as_slice().chars().take(EXCERPT_LEN).collect();
+    short.push_str("...");
+    short
+}
+
+// Body as text in the declared charset, UTF-8 by default
+fn decode_text(body: Vec<u8>, charset: Option<String>) -> Result<String, JsonErrorKind> {
+    let charset = charset.map(|c| c.as_slice().to_ascii_lower()).unwrap_or("utf-8".to_string());
+    match charset.as_slice() {
+        "utf-8" | "utf8" => {
+            let body = if body.as_slice().starts_with(b"\xef\xbb\xbf") { body.slice_from(3).to_vec() } else { body };
+            String::from_utf8(body).map_err(|_| MalformedText(charset.clone()))
+        },
+        "us-ascii" | "ascii" if body.iter().all(|&b| b < 0x80) => Ok(String::from_utf8(body).unwrap()),
+        "us-ascii" | "ascii" => Err(MalformedText(charset.clone())),
+        // Latin-1 bytes are the first 256 code points
+        "iso-8859-1" | "latin1" => Ok(body.iter().map(|&b| b as char).collect()),
+        _ => Err(UnsupportedCharset(charset.clone())),
+    }
+}
+
+/// Decodes JSON body of a response with the given status and headers
+pub fn from_body<T: Decodable<json::Decoder, json::DecoderError>>(status: u16, headers: &Headers,
+                                                                   body: Option<Vec<u8>>) -> Result<T, JsonError> {
+    let body = match body {
+        Some(body) => body,
+        None => return Err(JsonError { kind: MissingBody, status: status, excerpt: String::new() })
+    };
+    let error = |kind| JsonError {
+        kind: kind,
+        status: status,
+        excerpt: excerpt(body.as_slice()),
+    };
+
+    match headers.mime_type() {
+        Some(ref t) if is_json_type(t.as_slice()) => (),
+        other => return Err(error(WrongContentType(other)))
+    }
+
+    let text = match decode_text(body.clone(), headers.charset()) {
+        Ok(text) => text,
+        Err(kind) => return Err(error(kind))
+    };
+    match json::decode(text.as_slice()) {
+        Ok(value) => Ok(value),
+        Err(e) => Err(error(InvalidJson(e)))
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::{from_body, is_json_type, WrongContentType, InvalidJson};
+    use http::{Client, Headers, Response};
+
+    #[deriving(Encodable, Decodable, PartialEq, Show)]
+    struct Point {
+        x: int,
+        y: int,
+        label: String,
+    }
+
+    #[deriving(Decodable)]
+    struct Echo {
+        json: Point,
+    }
+
+    fn json_headers(content_type: &str) -> Headers {
+        let mut h = Headers::new();
+        h.append("Content-Type", content_type);
+        h
+    }
+
+    #[test]
+    fn content_types() {
+        assert!(is_json_type("application/json"));
+        assert!(is_json_type("application/problem+json"));
+        assert!(!is_json_type("text/html"));
+    }
+
+    #[test]
+    fn charsets() {
+        let h = json_headers("application/json; charset=ISO-8859-1");
+        let body = b"{\"x\": 1, \"y\": 2, \"label\": \"caf\xe9\"}".to_vec();
+        let p: Point = from_body(200, &h, Some(body)).unwrap();
+        assert_eq!(p.label.as_slice(), "café");
+
+        let h = json_headers("application/json");
+        let body = b"\xef\xbb\xbf{\"x\": 1, \"y\": 2, \"label\": \"\"}".to_vec();
+        let p: Point = from_body(200, &h, Some(body)).unwrap();
+        assert_eq!((p.x, p.y), (1, 2));
+    }
+
+    #[test]
+    fn errors() {
+        let mut resp = Response::synthetic(500, json_headers("text/html"), b"<h1>Oops</h1>".to_vec());
+        match resp.json::<Point>() {
+            Err(e) => {
+                assert!(match e.kind { WrongContentType(Some(ref t)) => t.as_slice() == "text/html", _ => false });
+                assert_eq!(e.status, 500);
+                assert_eq!(e.excerpt.as_slice(), "<h1>Oops</h1>");
+            },
+            Ok(_) => fail!("HTML is not JSON")
+        }
+
+        let body = Vec::from_elem(1000, b'x');
+        match from_body::<Point>(200, &json_headers("application/json"), Some(body)) {
+            Err(e) => {
+                assert!(match e.kind { InvalidJson(_) => true, _ => false });
+                assert_eq!(e.excerpt.len(), 203);
+            },
+            Ok(_) => fail!("garbage is not JSON")
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut c = Client::new("http://httpbin.org/");
+        let mut req = c.new_post_request("post");
+        let point = Point { x: 1, y: -2, label: "a \"b\"".to_string() };
+        req.json(&point);
+
+        let mut resp = c.perform(&mut req).unwrap();
+        assert_eq!(resp.status_code, 200);
+        assert_eq!(resp.json::<Echo>().unwrap().json, point);
+    }
+}
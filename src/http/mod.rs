@@ -0,0 +1,1579 @@
+use easy::{Curl};
+use info;
+use libc;
+use opt;
+use errors::{CURLE_OK, CURLE_BAD_FUNCTION_ARGUMENT, CURLE_READ_ERROR, CURLE_WRITE_ERROR,
+             CURLE_TOO_MANY_REDIRECTS, CURLE_URL_MALFORMAT};
+use handlers::{Handler, MemoryHandler};
+use mime::{Form, Mime};
+use url;
+use url::{Url, UrlError};
+use serialize;
+use serialize::{Decodable, Encodable};
+use std::ascii::StrAsciiExt;
+use std::collections::HashMap;
+use std::cell::RefCell;
+use std::io::{IoError, EndOfFile, OtherIoError, MemReader};
+use std::io::timer;
+use std::time::Duration;
+use std::{c_vec, mem, ptr, slice};
+use time;
+
+pub use self::auth::{Auth, Credentials, UserPassword, Token, CurrentUser, CredentialProvider,
+                     Scheme, BasicAuth, DigestAuth, NtlmAuth, NegotiateAuth, BearerAuth};
+pub use self::batch::Batch;
+pub use self::body::{Body, BodySource, Empty, Bytes, Text, FilePath, Stream, UrlEncoded, Multipart};
+pub use self::cache::{Cache, CacheStatus, CacheHit, CacheMiss, CacheRevalidated, CacheEntry,
+                      CacheStorage, MemoryStorage, DiskStorage, Validators};
+pub use self::cookies::{Cookie, CookieJar};
+pub use self::encoding::{AcceptEncoding, RawEncoding, AllSupported, Codings,
+                         Coding, Identity, Gzip, Deflate, Brotli, Zstd};
+pub use self::proxy::{Proxy, ProxyStatus, ProxyKind, HttpProxy, HttpsProxy,
+                      Socks4, Socks4a, Socks5, Socks5Hostname};
+#[cfg(target_os = "linux")]
+pub use self::future::{AsyncClient, ResponseFuture};
+pub use self::headers::{Headers, CacheControl};
+pub use self::interceptor::Interceptor;
+pub use self::json::{JsonError, JsonErrorKind, MissingBody, BodyReadError, WrongContentType,
+                     UnsupportedCharset, MalformedText, InvalidJson};
+pub use self::pool::{Pool, PoolLimits, PoolStats, PooledClient};
+pub use self::redirect::{Redirect, RedirectFilter, RedirectPolicy};
+pub use self::retry::{RetryPolicy, RetryAttempt};
+pub use self::tls::{TlsConfig, TlsVersion, Tls10, Tls11, Tls12, Tls13, CertFormat, Pem, Der, P12,
+                    Revocation, DefaultRevocation, NoRevocation, BestEffortRevocation, OcspStapling};
+pub use self::parser::{HeadParser, ResponseHead, StatusLine, Version,
+                       Http10, Http11, Http2, Http3, OtherVersion};
+
+pub mod auth;
+pub mod batch;
+pub mod body;
+pub mod cache;
+pub mod cookies;
+pub mod download;
+pub mod encoding;
+#[cfg(target_os = "linux")]
+pub mod future;
+pub mod headers;
+pub mod interceptor;
+pub mod json;
+pub mod parser;
+pub mod pool;
+pub mod proxy;
+pub mod redirect;
+pub mod retry;
+mod stream;
+pub mod tls;
+mod transfer;
+
+pub static CURL_ERROR_SIZE: uint = 256;
+
+static CURL_READFUNC_ABORT: libc::size_t = 0x10000000;
+static CURL_SEEKFUNC_OK: libc::c_int = 0;
+static CURL_SEEKFUNC_FAIL: libc::c_int = 1;
+static CURL_SEEKFUNC_CANTSEEK: libc::c_int = 2;
+
+// CURL_TIMECOND_* values
+static TIMECOND_NONE: int = 0;
+static TIMECOND_IFMODSINCE: int = 1;
+
+/// HTTP Method, nuff said
+///
+/// Custom uses a static str as it is hard
+/// to believe that anything else might happen,
+/// i.e. that method name will be generated
+/// dynamically
+pub enum Method {
+    Get,
+    Post,
+    Delete,
+    Head,
+    Put,
+    Patch,
+    Custom(&'static str)
+}
+
+#[deriving(Show)]
+pub struct CurlError {
+    pub code: uint,
+    pub message: String,
+    /// What the proxy answered, if the transfer got to it
+    pub proxy: Option<ProxyStatus>,
+    /// All attempts if there is a retry policy
+    pub attempts: Vec<RetryAttempt>,
+}
+
+type ResponseWriteClosure<'a> = |&c_vec::CVec<u8>|:'a -> uint;
+
+/// Credential provider is asked that many times per request
+static MAX_AUTH_ATTEMPTS: uint = 3;
+
+// Outcome of a single transfer of `Client::perform`
+struct Attempt {
+    code: uint,
+    response: Response,
+    write_error: Option<IoError>,
+    head_done: bool,
+    // Body of 401 response which wasn't passed to the handler
+    held_back: Option<Vec<u8>>,
+    proxy: Option<ProxyStatus>,
+    // Resolved `Location` of a redirect
+    redirect_url: Option<String>,
+}
+
+// Where `Client::perform` sends the request, differs from
+// the request itself once a redirect is followed
+struct Hop {
+    url: String,
+    method: Method,
+    body: bool,
+    credentials: bool,
+    referer: Option<String>,
+    // Makes the request conditional
+    validators: Option<Validators>,
+}
+
+/// The general HTTP client which is tied to a specific
+/// base URL and allows easy construction of relative requests
+///
+/// Cookies are automatically shared between all requests,
+/// see `cookie_jar` to persist or edit them
+pub struct Client {
+    base_url: String,
+    session: Curl,
+    error_buf: Vec<u8>,
+    auth: Option<Auth>,
+    credential_provider: Option<Box<CredentialProvider+Send>>,
+    proxy: Option<Proxy>,
+    proxy_from_env: bool,
+    retry: Option<RetryPolicy>,
+    accept_encoding: AcceptEncoding,
+    cache: Option<Cache>,
+    interceptors: Vec<Box<Interceptor+Send>>,
+}
+
+/// How the response was received, as reported by libcurl
+#[deriving(Clone, Show, PartialEq)]
+pub struct ConnectionInfo {
+    /// Address of the last connected server (or proxy)
+    pub primary_ip: String,
+    pub primary_port: u16,
+    pub local_ip: String,
+    pub local_port: u16,
+    /// New connections made for the transfer, 0 means
+    /// an existing keep-alive connection was reused
+    pub num_connects: uint,
+    /// Status of the proxy CONNECT response, 0 without tunnel
+    pub connect_code: u16,
+    pub redirect_count: uint,
+    /// Negotiated HTTP version
+    pub http_version: Option<Version>,
+}
+
+/// Represents HTTP response
+pub struct Response {
+    pub url: String,
+    pub headers: Headers,
+    pub status_code: u16,
+    pub status_message: String,
+    /// Version from the final status line
+    pub version: Option<Version>,
+    /// Heads received before the final one: informational (1xx),
+    /// proxy CONNECT and, when `perform_streaming` follows redirects,
+    /// redirect responses
+    pub interim: Vec<ResponseHead>,
+    /// Trailer fields of a chunked body
+    pub trailers: Headers,
+    /// Schemes from `WWW-Authenticate` of the final response
+    pub auth_offered: Vec<Scheme>,
+    /// All attempts if there is a retry policy
+    pub attempts: Vec<RetryAttempt>,
+    /// Redirects followed by `perform`, in order
+    pub history: Vec<Redirect>,
+    /// `Content-Encoding` the body was received with,
+    /// `content_data` is decoded unless it is `RawEncoding`
+    pub content_encoding: Option<String>,
+    /// Body size as received, i.e. before decoding,
+    /// unknown while `perform_streaming` reads the body
+    pub compressed_size: Option<u64>,
+    /// Set if the client has a cache
+    pub cache_status: Option<CacheStatus>,
+    pub connection: ConnectionInfo,
+    pub content_data: Option<Box<Reader+'static>>,
+}
+
+/// Represents HTTP request
+pub struct Request {
+    url: String,
+
+    pub headers: HashMap<String, String>,
+    pub method: Method,
+    pub redirects: RedirectPolicy,
+
+    /// Transfer timeout in seconds
+    pub timeout: Option<uint>,
+    /// Connection timeout in seconds
+    pub connection_timeout: Option<uint>,
+
+    pub body: Body,
+
+    /// Download offset, i.e. size of already received part
+    pub resume_from: Option<u64>,
+
+    pub auth: Option<Auth>,
+
+    /// Replaces client TLS settings, request goes
+    /// through a separate connection then
+    pub tls: Option<TlsConfig>,
+
+    /// Content codings asked from the server, the body
+    /// is decoded before it reaches the handler
+    pub accept_encoding: AcceptEncoding,
+
+    // Taken by perform, body is collected in memory if not set
+    handler: RefCell<Option<Box<Handler+Send>>>,
+
+    // Set if the client couldn't resolve the URL
+    url_error: Option<String>,
+}
+
+impl CurlError {
+    pub fn new(code: uint, message: String) -> CurlError {
+        CurlError {
+            code: code,
+            message: message,
+            proxy: None,
+            attempts: Vec::new(),
+        }
+    }
+}
+
+impl Client {
+    /// Constructs a new client with a base URL
+    pub fn new(base_url: &str) -> Client {
+        let session = Curl::new();
+        session.setopt(opt::NOSIGNAL, true);
+
+        let mut error_buf = Vec::with_capacity(CURL_ERROR_SIZE + 1);
+
+        // Setup all default functions at once
+        session.set_data_func(opt::WRITEFUNCTION, Client::http_write_fn);
+        session.set_data_func(opt::HEADERFUNCTION, Client::http_header_fn);
+        session.set_data_func(opt::READFUNCTION, Client::http_read_fn);
+        session.set_seek_func(Client::http_seek_fn);
+        session.set_progress_func(Client::http_progress_fn);
+
+        // FIXME: check on practice if ERRORBUFFER provides
+        // more verbosity than just using strerror as the latter
+        // one sounds easier
+        session.setopt(opt::ERRORBUFFER, error_buf.as_mut_ptr());
+
+        // Empty file name just turns the cookie engine on
+        session.setopt(opt::COOKIEFILE, "");
+
+        Client {
+            base_url: base_url.to_string(),
+            session: session,
+            error_buf: error_buf,
+            auth: None,
+            credential_provider: None,
+            proxy: None,
+            proxy_from_env: true,
+            retry: None,
+            accept_encoding: RawEncoding,
+            cache: None,
+            interceptors: Vec::new(),
+        }
+    }
+
+    /// Resolves `rel_url` against `base_url` as described in RFC 3986,
+    /// except that base path is always a "directory" like it was
+    /// before, i.e. `http://h/api` and `users` give `http://h/api/users`
+    /// rather than `http://h/users`. Rooted and absolute references
+    /// replace the base path as usual.
+    fn get_rel_url(base_url: &str, rel_url: &str) -> Result<String, UrlError> {
+        let mut base = try!(Url::parse(base_url));
+        match base.path() {
+            Some(ref path) if !path.as_slice().ends_with("/") => {
+                try!(base.set_path(format!("{}/", path).as_slice()));
+            },
+            _ => ()
+        }
+        base.join(rel_url).map(|url| url.as_string())
+    }
+
+    /// Cookies received and sent by this client
+    pub fn cookie_jar<'a>(&'a mut self) -> CookieJar<'a> {
+        CookieJar::new(&self.session)
+    }
+
+    /// Authentication of requests constructed by this client
+    pub fn set_auth(&mut self, auth: Auth) {
+        self.auth = Some(auth);
+    }
+
+    /// Provider asked for credentials when server answers 401
+    pub fn set_credential_provider(&mut self, provider: Box<CredentialProvider+Send>) {
+        self.credential_provider = Some(provider);
+    }
+
+    /// Proxy for all further requests, `None` connects directly
+    /// or through a proxy from environment, see `set_proxy_from_env`
+    pub fn set_proxy(&mut self, proxy: Option<Proxy>) {
+        self.proxy = proxy;
+        self.apply_proxy();
+    }
+
+    /// Whether `http_proxy`, `https_proxy`, `all_proxy` and `no_proxy`
+    /// environment variables are used if there's no proxy set, on by default
+    pub fn set_proxy_from_env(&mut self, from_env: bool) {
+        self.proxy_from_env = from_env;
+        self.apply_proxy();
+    }
+
+    fn apply_proxy(&self) {
+        match self.proxy {
+            Some(ref proxy) => proxy.apply(&self.session),
+            None => Proxy::reset(&self.session, self.proxy_from_env),
+        }
+    }
+
+    /// Content codings asked for by requests constructed by this client
+    pub fn set_accept_encoding(&mut self, encoding: AcceptEncoding) {
+        self.accept_encoding = encoding;
+    }
+
+    /// Cache for all further requests, `None` turns it off
+    pub fn set_cache(&mut self, cache: Option<Cache>) {
+        self.cache = cache;
+    }
+
+    /// Adds an interceptor after the ones added before
+    pub fn add_interceptor(&mut self, interceptor: Box<Interceptor+Send>) {
+        self.interceptors.push(interceptor);
+    }
+
+    /// Makes `perform` repeat failed requests
+    pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+        self.retry = Some(policy);
+    }
+
+    /// TLS settings for all further requests, replacing previous
+    /// ones completely. `Request::tls` overrides them.
+    pub fn set_tls(&mut self, tls: TlsConfig) {
+        tls.apply(&self.session);
+    }
+
+    /// Constructs GET request relatively to base URL
+    pub fn new_get_request(&self, rel_url: &str) -> Request {
+        self.new_request(rel_url, Get)
+    }
+
+    /// Constructs POST request relatively to base URL
+    pub fn new_post_request(&self, rel_url: &str) -> Request {
+        self.new_request(rel_url, Post)
+    }
+
+    /// Constructs PUT request relatively to base URL
+    pub fn new_put_request(&self, rel_url: &str) -> Request {
+        self.new_request(rel_url, Put)
+    }
+
+    /// Constructs PATCH request relatively to base URL
+    pub fn new_patch_request(&self, rel_url: &str) -> Request {
+        self.new_request(rel_url, Patch)
+    }
+
+    // URL which can't be resolved is an error of `perform`
+    fn new_request(&self, rel_url: &str, method: Method) -> Request {
+        let mut req = match Client::get_rel_url(self.base_url.as_slice(), rel_url) {
+            Ok(url) => Request::new(url.as_slice(), method),
+            Err(e) => {
+                let mut req = Request::new(rel_url, method);
+                req.url_error = Some(format!("Can't resolve {} against {}: {}", rel_url, self.base_url, e.message));
+                req
+            }
+        };
+        req.auth = self.auth.clone();
+        req.accept_encoding = self.accept_encoding.clone();
+        req
+    }
+
+    // Body size is -1 if unknown, None if there is no body
+    // which goes through the read callback
+    // Body of GET, DELETE and custom methods is sent as of POST
+    // under the method name, `multipart` bodies have no size here
+    fn update_for_method(session: &Curl, method: Method, body_size: Option<i64>,
+                         multipart: bool) -> Result<(), CurlError> {
+        // Session is reused, so drop whatever previous request has set.
+        // HTTPGET also resets NOBODY and UPLOAD
+        session.setopt(opt::HTTPGET, true);
+        session.setopt(opt::CUSTOMREQUEST, 0u);
+
+        let has_body = body_size.is_some() || multipart;
+        match method {
+            Get if !has_body => (),
+            Head if has_body => {
+                return Err(CurlError::new(CURLE_BAD_FUNCTION_ARGUMENT as uint,
+                                          "HEAD request can't have a body".to_string()));
+            },
+            Post => {
+                session.setopt(opt::POST, true);
+                session.setopt(opt::POSTFIELDSIZE_LARGE, body_size.unwrap_or(0));
+            },
+            // MIMEPOST would turn an upload into a POST
+            Put if multipart => { session.setopt(opt::CUSTOMREQUEST, "PUT"); },
+            Put => {
+                session.setopt(opt::UPLOAD, true);
+                session.setopt(opt::INFILESIZE_LARGE, body_size.unwrap_or(0));
+            },
+            Patch => {
+                // UPLOAD makes it a PUT, so the name is replaced
+                session.setopt(opt::UPLOAD, true);
+                session.setopt(opt::INFILESIZE_LARGE, body_size.unwrap_or(0));
+                session.setopt(opt::CUSTOMREQUEST, "PATCH");
+            },
+            Get | Delete | Custom(_) => {
+                match body_size {
+                    Some(size) => {
+                        session.setopt(opt::POST, true);
+                        session.setopt(opt::POSTFIELDSIZE_LARGE, size);
+                    },
+                    None => ()
+                }
+                let name = match method {
+                    Get => "GET",
+                    Delete => "DELETE",
+                    Custom(name) => name,
+                    _ => unreachable!()
+                };
+                session.setopt(opt::CUSTOMREQUEST, name);
+            },
+            Head => { session.setopt(opt::NOBODY, true); },
+        }
+        Ok(())
+    }
+
+    // Applies request options to the handle. Returned body source
+    // and mime have to stay alive until the transfer is over,
+    // READDATA and SEEKDATA are set by the caller as the source is moved out.
+    // Redirects are not followed, see `RedirectPolicy::apply`.
+    fn prepare<'a>(session: &Curl, req: &'a Request, hop: &Hop)
+                   -> Result<(Option<BodySource<'a>>, Option<Mime>), CurlError> {
+        match req.url_error {
+            Some(ref msg) => return Err(CurlError::new(CURLE_URL_MALFORMAT as uint, msg.clone())),
+            None => ()
+        }
+        let _ = session.setopt(opt::URL, hop.url.as_slice());
+        let _ = session.setopt(opt::USERAGENT, "CRust/0.0.1");
+
+        // Everything except multipart goes through http_read_fn
+        // Body dropped by a redirect isn't even opened
+        let source = if !hop.body {
+            None
+        } else {
+            match BodySource::new(&req.body) {
+                Ok(source) => source,
+                Err(e) => return Err(CurlError::new(CURLE_READ_ERROR as uint, e.to_string())),
+            }
+        };
+        let body_size = source.as_ref().map(|s| s.len.map(|l| l as i64).unwrap_or(-1));
+
+        let mut header_vec: Vec<String> = req.headers.iter()
+            .filter(|&(k, _)| hop.body || !k.as_slice().eq_ignore_ascii_case("Content-Type"))
+            .filter(|&(k, _)| hop.credentials || !is_credential_header(k.as_slice()))
+            .map(|(k, v)| format!("{}: {}", k, v))
+            .collect();
+        match req.body.default_content_type() {
+            Some(ct) if hop.body && !req.has_header("Content-Type") => header_vec.push(format!("Content-Type: {}", ct)),
+            _ => ()
+        }
+        match hop.validators {
+            Some(Validators { etag: Some(ref etag), .. }) if !req.has_header("If-None-Match") => {
+                header_vec.push(format!("If-None-Match: {}", etag))
+            },
+            _ => ()
+        }
+        match (hop.method, body_size) {
+            // Uploads switch to chunked encoding themselves, bodies sent as of POST need a hint
+            (Put, _) | (Patch, _) => (),
+            (_, Some(-1)) => header_vec.push("Transfer-Encoding: chunked".to_string()),
+            _ => ()
+        }
+        // Empty list clears headers of the previous request
+        if header_vec.len() > 0 {
+            session.setopt(opt::HTTPHEADER, header_vec);
+        } else {
+            session.setopt(opt::HTTPHEADER, 0u);
+        }
+        let _ = session.setopt(opt::VERBOSE, false);
+        let multipart = match req.body {
+            Multipart(_) => hop.body,
+            _ => false
+        };
+        try!(Client::update_for_method(session, hop.method, body_size, multipart));
+        let _ = session.setopt(opt::FOLLOWLOCATION, false);
+        let _ = session.setopt(opt::AUTOREFERER, false);
+        match hop.referer {
+            Some(ref referer) => session.setopt(opt::REFERER, referer.as_slice()),
+            None => session.setopt(opt::REFERER, 0u),
+        };
+
+        if req.timeout.is_some() {
+            session.setopt(opt::TIMEOUT, req.timeout.unwrap() as int);
+        }
+
+        if req.connection_timeout.is_some() {
+            // FIXME: check if it will work correctly with NOSIGNAL
+            session.setopt(opt::CONNECTTIMEOUT, req.connection_timeout.unwrap());
+        }
+
+        match req.auth {
+            Some(ref auth) if hop.credentials => auth.apply(session),
+            _ => Auth::reset(session),
+        }
+
+        req.accept_encoding.apply(session);
+
+        // libcurl sends If-Modified-Since itself
+        match hop.validators {
+            Some(Validators { last_modified: Some(ref modified), .. }) => {
+                session.setopt(opt::TIMECONDITION, TIMECOND_IFMODSINCE);
+                session.setopt(opt::TIMEVALUE, modified.to_timespec().sec as int);
+            },
+            _ => { session.setopt(opt::TIMECONDITION, TIMECOND_NONE); }
+        }
+
+        // Range header is set by libcurl, 0 disables it
+        session.setopt(opt::RESUME_FROM_LARGE, req.resume_from.unwrap_or(0) as i64);
+
+        let mime = match req.body {
+            Multipart(ref form) if hop.body => match Mime::new(session, form) {
+                Ok(mime) => match mime.attach(session) {
+                    0 => Some(mime),
+                    code => return Err(CurlError::new(code as uint, "Can't attach multipart body".to_string())),
+                },
+                Err(msg) => return Err(CurlError::new(CURLE_BAD_FUNCTION_ARGUMENT as uint, msg)),
+            },
+            _ => None
+        };
+
+        Ok((source, mime))
+    }
+
+    // Read and seek callbacks share the body source, null means no body
+    fn set_source(session: &Curl, source: *mut BodySource) {
+        session.setopt(opt::READDATA, source);
+        session.setopt(opt::SEEKDATA, source);
+    }
+
+    // Cleanup any unsafe data which is bound to current request
+    fn cleanup(session: &Curl, mime: Option<Mime>) {
+        session.setopt(opt::HEADERDATA, 0u);
+        session.setopt(opt::WRITEDATA, 0u);
+        Client::set_source(session, ptr::mut_null());
+        if mime.is_some() {
+            session.setopt(opt::MIMEPOST, 0u);
+        }
+    }
+
+    // Fills response fields which are known once headers are received
+    fn fill_response(session: &Curl, response: &mut Response) {
+        let val: Option<int> = session.getinfo(info::RESPONSE_CODE);
+        response.status_code = val.unwrap_or(0) as u16;
+
+        let val: Option<String> = session.getinfo(info::EFFECTIVE_URL);
+        response.url = val.unwrap_or(String::new());
+
+        response.connection = ConnectionInfo::from_session(session);
+
+        // Counted before decoding
+        response.content_encoding = response.headers.get("Content-Encoding").map(|v| v.to_string());
+        if response.content_encoding.is_some() {
+            let val: Option<f64> = session.getinfo(info::SIZE_DOWNLOAD);
+            response.compressed_size = val.map(|size| size as u64);
+        }
+    }
+
+    /// Sends request to server and returns a response (if any)
+    ///
+    /// Request goes through interceptors first, they may change it.
+    /// With a cache, fresh stored responses are served without
+    /// a request and stale ones are revalidated, see `Cache`.
+    /// If the response is 401 and there is a credential provider,
+    /// the request is repeated with credentials it gives. Failed
+    /// requests are repeated as the retry policy says.
+    pub fn perform(&mut self, req: &mut Request) -> Result<Response, CurlError> {
+        if self.interceptors.is_empty() {
+            return self.transfer(req);
+        }
+
+        // Taken out, so the client can be borrowed while they run
+        let mut interceptors = mem::replace(&mut self.interceptors, Vec::new());
+        let res = interceptor::intercept(&mut interceptors, req, |req| self.transfer(req));
+        self.interceptors = interceptors;
+        res
+    }
+
+    fn transfer(&mut self, req: &Request) -> Result<Response, CurlError> {
+        let handler = match req.handler.borrow_mut().take() {
+            Some(h) => h,
+            None => box MemoryHandler::new() as Box<Handler+Send>,
+        };
+        match self.cache.take() {
+            Some(mut cache) => {
+                let res = cache::perform(&mut cache, req, handler,
+                                         |handler, validators| self.exchange(req, handler, validators));
+                self.cache = Some(cache);
+                res
+            },
+            None => self.exchange(req, handler, None)
+        }
+    }
+
+    // Performs the request with auth, retries and redirects,
+    // `validators` make the first hop conditional
+    fn exchange(&mut self, req: &Request, mut handler: Box<Handler+Send>,
+                validators: Option<Validators>) -> Result<Response, CurlError> {
+        if req.accept_encoding.decodes_in_rust() {
+            handler = box encoding::Decoder::new(handler) as Box<Handler+Send>;
+        }
+        let mut auth = None;
+        let mut auth_attempts = 0u;
+        let mut hop = Hop::first(req);
+        hop.validators = validators;
+        let mut redirects = Vec::new();
+
+        let retry = match self.retry {
+            Some(ref policy) if policy.allows(req.method) && req.body.is_replayable() => Some(policy.clone()),
+            _ => None
+        };
+        let mut history = Vec::new();
+        // Retries are counted for each hop separately,
+        // the deadline is for the whole perform
+        let mut hop_attempts = 0u;
+        let started = time::precise_time_ns();
+        let elapsed_ms = |since: u64| (time::precise_time_ns() - since) / 1000000;
+
+        loop {
+            let can_authenticate = self.credential_provider.is_some() &&
+                auth_attempts < MAX_AUTH_ATTEMPTS && req.body.is_replayable();
+
+            // Bodies of responses which may be repeated don't go to the handler
+            let mut hold = Vec::new();
+            if can_authenticate {
+                hold.push(401);
+            }
+            match retry {
+                Some(ref policy) if hop_attempts + 1 < policy.max_attempts => {
+                    hold.push_all(policy.statuses.as_slice())
+                },
+                _ => ()
+            }
+            if req.redirects.max_redirects > 0 {
+                hold.push_all([301, 302, 303, 307, 308]);
+            }
+
+            let attempt_started = time::precise_time_ns();
+            let mut attempt = try!(self.attempt(req, &hop, &mut handler, auth.as_ref(), hold.as_slice()));
+            hop_attempts += 1;
+            let code = attempt.code as libc::c_uint;
+            let status = attempt.response.status_code;
+
+            let mut repeat = false;
+            let mut delay = 0;
+            // Asking for credentials is not a failure to retry
+            let mut auth_round = false;
+            match (code, attempt.held_back.take()) {
+                (CURLE_OK, Some(body)) => {
+                    if status == 401 && can_authenticate {
+                        auth_attempts += 1;
+                        auth = {
+                            let resp = &attempt.response;
+                            let provider = self.credential_provider.as_mut().unwrap();
+                            provider.credentials(resp.url.as_slice(), resp.auth_offered.as_slice())
+                        };
+                        repeat = auth.is_some();
+                        if repeat {
+                            auth_round = true;
+                            hop_attempts -= 1;
+                        }
+                    } else {
+                        let headers = &attempt.response.headers;
+                        match retry.as_ref().and_then(|p| p.delay(hop_attempts, elapsed_ms(started), Some(headers))) {
+                            Some(ms) => {
+                                repeat = true;
+                                delay = ms;
+                            },
+                            None => ()
+                        }
+                    }
+
+                    if !repeat && req.redirects.max_redirects > 0 && RedirectPolicy::is_redirect(status) {
+                        let resp = &attempt.response;
+                        let redirect = Redirect {
+                            url: resp.url.clone(),
+                            status: status,
+                            headers: resp.headers.clone(),
+                        };
+                        let next = attempt.redirect_url.as_ref()
+                            .and_then(|target| hop.next(req, &redirect, target.as_slice()));
+                        match next {
+                            Some(_) if redirects.len() >= req.redirects.max_redirects => {
+                                handler.abort();
+                                let mut err = CurlError::new(CURLE_TOO_MANY_REDIRECTS as uint,
+                                    format!("Maximum ({}) redirects followed", req.redirects.max_redirects));
+                                err.attempts = history;
+                                return Err(err);
+                            },
+                            Some(next) => {
+                                debug!("Redirected from {} to {}", hop.url, next.url);
+                                redirects.push(redirect);
+                                hop = next;
+                                // Credentials given for the previous hop
+                                auth = None;
+                                auth_attempts = 0;
+                                hop_attempts = 0;
+                                repeat = true;
+                            },
+                            None => ()
+                        }
+                    }
+
+                    if !repeat {
+                        // Nobody to ask or no more attempts,
+                        // so the handler gets the response after all
+                        let resp = &attempt.response;
+                        attempt.write_error = handler.head(resp.status_code, &resp.headers)
+                            .and_then(|_| handler.write(body.as_slice()))
+                            .err();
+                        attempt.head_done = true;
+                    }
+                },
+                (CURLE_OK, None) => (),
+                // Handler has nothing yet, so it is safe to start over
+                (_, _) if !attempt.head_done => {
+                    match retry {
+                        Some(ref p) if p.errors.contains(&(code as uint)) => {
+                            match p.delay(hop_attempts, elapsed_ms(started), None) {
+                                Some(ms) => {
+                                    repeat = true;
+                                    delay = ms;
+                                },
+                                None => ()
+                            }
+                        },
+                        _ => ()
+                    }
+                },
+                _ => ()
+            }
+
+            if retry.is_some() && !auth_round {
+                history.push(RetryAttempt {
+                    status: if code == CURLE_OK { Some(status) } else { None },
+                    error: if code == CURLE_OK { None } else { Some(code as uint) },
+                    duration_ms: elapsed_ms(attempt_started),
+                    delay_ms: delay,
+                });
+            }
+
+            if repeat {
+                if delay > 0 {
+                    debug!("Repeating request to {} in {} ms", hop.url, delay);
+                    timer::sleep(Duration::milliseconds(delay as i64));
+                }
+                continue;
+            }
+
+            return match self.complete(attempt, handler, history) {
+                Ok(mut resp) => {
+                    resp.history = redirects;
+                    Ok(resp)
+                },
+                Err(e) => Err(e)
+            };
+        }
+    }
+
+    // Performs the transfer once, body goes to the handler unless
+    // the status is in `hold`, i.e. request may be repeated
+    fn attempt(&self, req: &Request, hop: &Hop, handler: &mut Box<Handler+Send>,
+               auth: Option<&Auth>, hold: &[u16]) -> Result<Attempt, CurlError> {
+        // Request TLS settings go to a copy of the session, so
+        // the connection doesn't mix with the client ones. Copy
+        // works with the client cookies and gives them back.
+        let tls_session;
+        let session = match req.tls {
+            Some(ref tls) => {
+                tls_session = self.session.duphandle();
+                // Client writes the jar file, not the copy
+                tls_session.setopt(opt::COOKIEJAR, 0u);
+                copy_cookies(&self.session, &tls_session);
+                tls.apply(&tls_session);
+                &tls_session
+            },
+            None => &self.session,
+        };
+
+        let (mut source, mime) = try!(Client::prepare(session, req, hop));
+        match auth {
+            Some(auth) => auth.apply(session),
+            None => ()
+        }
+
+        let mut write_error = None;
+        let mut head_done = false;
+        let mut held_back = None;
+        let mut parser = HeadParser::new();
+        let mut response = Response::new();
+
+        let res = {
+            session.setopt(opt::HEADERDATA, &mut parser as *mut HeadParser);
+
+            let no_headers = Headers::new();
+            let mut cl: ResponseWriteClosure = |buf| {
+                if !head_done {
+                    head_done = true;
+                    let code: Option<int> = session.getinfo(info::RESPONSE_CODE);
+                    let code = code.unwrap_or(0) as u16;
+                    if hold.contains(&code) {
+                        held_back = Some(Vec::new());
+                    } else {
+                        let headers = parser.last().map(|h| &h.headers).unwrap_or(&no_headers);
+                        match handler.head(code, headers) {
+                            Ok(()) => (),
+                            Err(e) => {
+                                write_error = Some(e);
+                                return 0;
+                            }
+                        }
+                    }
+                }
+                match held_back {
+                    Some(ref mut body) => {
+                        body.push_all(buf.as_slice());
+                        return buf.len();
+                    },
+                    None => ()
+                }
+                match handler.write(buf.as_slice()) {
+                    Ok(_) => buf.len(),
+                    Err(e) => {
+                        write_error = Some(e);
+                        0
+                    }
+                }
+            };
+            session.setopt(opt::WRITEDATA, &mut cl as *mut ResponseWriteClosure);
+
+            match source {
+                Some(ref mut s) => Client::set_source(session, s as *mut BodySource),
+                None => Client::set_source(session, ptr::mut_null()),
+            }
+
+            session.perform()
+        };
+
+        Client::cleanup(session, mime);
+        response.set_heads(parser);
+        if req.tls.is_some() {
+            copy_cookies(session, &self.session);
+        }
+
+        let mut proxy = None;
+        let mut redirect_url = None;
+        if res as libc::c_uint == CURLE_OK {
+            Client::fill_response(session, &mut response);
+            redirect_url = session.getinfo(info::REDIRECT_URL);
+
+            // libcurl drops body of a 200 which is not newer
+            // than If-Modified-Since, so it is the same as 304
+            let unmet: Option<int> = session.getinfo(info::CONDITION_UNMET);
+            if hop.validators.is_some() && unmet == Some(1) {
+                response.status_code = 304;
+            }
+
+            // Body-less response
+            if !head_done && hold.contains(&response.status_code) {
+                head_done = true;
+                held_back = Some(Vec::new());
+            }
+        } else {
+            proxy = ProxyStatus::from_session(session);
+        }
+
+        Ok(Attempt {
+            code: res,
+            response: response,
+            write_error: write_error,
+            head_done: head_done,
+            held_back: held_back,
+            proxy: proxy,
+            redirect_url: redirect_url,
+        })
+    }
+
+    fn complete(&self, attempt: Attempt, mut handler: Box<Handler+Send>,
+                history: Vec<RetryAttempt>) -> Result<Response, CurlError> {
+        let Attempt { code, mut response, write_error, head_done, proxy, .. } = attempt;
+
+        match code as libc::c_uint {
+            CURLE_OK => {
+                // Body-less responses still have a head
+                let head = match write_error {
+                    Some(e) => Err(e),
+                    None if head_done => Ok(()),
+                    None => handler.head(response.status_code, &response.headers),
+                };
+                match head.and_then(|_| handler.finish()) {
+                    Ok(()) => {
+                        response.content_data = handler.into_reader();
+                        response.attempts = history;
+
+                        Ok(response)
+                    },
+                    Err(e) => {
+                        handler.abort();
+                        let mut err = CurlError::new(CURLE_WRITE_ERROR as uint, e.to_string());
+                        err.attempts = history;
+                        Err(err)
+                    }
+                }
+            },
+            _ => {
+                handler.abort();
+                let mut err = CurlError::new(code, match write_error {
+                    Some(e) => e.to_string(),
+                    None => self.error_buf.as_slice().to_c_str().as_str().unwrap().to_string()
+                });
+                err.proxy = proxy;
+                err.attempts = history;
+                Err(err)
+            },
+        }
+    }
+
+    /// Sends request to server and returns as soon as the response
+    /// head is received, `content_data` then reads the body while
+    /// it is being downloaded, so it is never buffered as a whole.
+    ///
+    /// Transfer runs on a duplicate of the session, so the client
+    /// may be used for other requests while the body is read.
+    /// Cookies of the response go to the client before it is returned.
+    /// Request handler is not used. Redirects are followed by libcurl,
+    /// so `Response::history` is empty and policies with `same_host`
+    /// or a filter are refused. Body is decoded only if libcurl
+    /// supports the content coding.
+    pub fn perform_streaming(&mut self, req: Request) -> Result<Response, CurlError> {
+        stream::start(&self.session, req)
+    }
+
+    /// Performs requests concurrently on this thread through
+    /// the multi interface, at most `max_concurrent` at once
+    /// (0 is no limit). Results are in the order of requests.
+    ///
+    /// Transfers run on copies of the session like in
+    /// `perform_streaming`, with the same restrictions on redirects.
+    /// Request handlers are used. Interceptors, cache, retry policy
+    /// and credential provider are not. Requests to the same host
+    /// share a connection if HTTP/2 is negotiated.
+    pub fn perform_all(&mut self, reqs: Vec<Request>, max_concurrent: uint) -> Vec<Result<Response, CurlError>> {
+        let mut results: Vec<Option<Result<Response, CurlError>>> = Vec::from_fn(reqs.len(), |_| None);
+        for (index, result) in self.perform_each(reqs, max_concurrent) {
+            *results.get_mut(index) = Some(result);
+        }
+        results.into_iter().map(|r| r.unwrap()).collect()
+    }
+
+    /// Same as `perform_all`, but yields `(index, result)`
+    /// as soon as a request completes
+    pub fn perform_each<'a>(&'a mut self, reqs: Vec<Request>, max_concurrent: uint) -> Batch<'a> {
+        batch::start(&self.session, reqs, max_concurrent)
+    }
+
+    // Curl callbacks implementations
+    // Write expects user_data to be ptr to closure f: |&CVec<u8>| -> uint
+    // which should write that buffer anywhere it wants
+    #[allow(unused_variable)]
+    fn http_write_fn(p: *mut u8, size: libc::size_t, nmemb: libc::size_t,
+                     user_data: *mut libc::c_void) -> libc::size_t {
+        let resp: *mut ResponseWriteClosure = unsafe { mem::transmute(user_data) };
+        if resp == ptr::mut_null() {
+            size * nmemb
+        } else {
+            unsafe {
+                let buf = c_vec::CVec::new(p, (size * nmemb) as uint);
+                (*resp)(&buf) as libc::size_t
+            }
+        }
+    }
+
+    // Read expects user_data to be *BodySource,
+    // null means there is no body to send
+    fn http_read_fn(p: *mut u8, size: libc::size_t, nmemb: libc::size_t,
+                    user_data: *mut libc::c_void) -> libc::size_t {
+        let source: *mut BodySource = unsafe { mem::transmute(user_data) };
+        if source == ptr::mut_null() {
+            return 0;
+        }
+
+        unsafe {
+            slice::raw::mut_buf_as_slice(p, (size * nmemb) as uint, |buf| {
+                match (*source).read(buf) {
+                    Ok(n) => n as libc::size_t,
+                    Err(IoError { kind: EndOfFile, .. }) => 0,
+                    Err(e) => {
+                        debug!("Request body read failed: {}", e);
+                        CURL_READFUNC_ABORT
+                    }
+                }
+            })
+        }
+    }
+
+    // Seek expects user_data to be *BodySource too, libcurl
+    // rewinds the body to send it again on a redirect or auth round
+    fn http_seek_fn(user_data: *mut libc::c_void, offset: i64, origin: libc::c_int) -> libc::c_int {
+        let source: *mut BodySource = unsafe { mem::transmute(user_data) };
+        // Only SEEK_SET is used by libcurl
+        if source == ptr::mut_null() || origin != 0 || offset < 0 {
+            return CURL_SEEKFUNC_CANTSEEK;
+        }
+
+        match unsafe { (*source).seek(offset as u64) } {
+            Ok(()) => CURL_SEEKFUNC_OK,
+            Err(IoError { kind: OtherIoError, .. }) => CURL_SEEKFUNC_CANTSEEK,
+            Err(e) => {
+                debug!("Request body seek failed: {}", e);
+                CURL_SEEKFUNC_FAIL
+            }
+        }
+    }
+
+    // Header expects user_data to be *HeadParser
+    fn http_header_fn(p: *mut u8, size: libc::size_t, nmemb: libc::size_t,
+                      user_data: *mut libc::c_void) -> libc::size_t {
+        let parser: *mut HeadParser = unsafe { mem::transmute(user_data) };
+        if parser != ptr::mut_null() {
+            unsafe {
+                slice::raw::buf_as_slice(p as *const u8, (size * nmemb) as uint, |line| {
+                    (*parser).feed(line);
+                });
+            }
+        }
+        size * nmemb
+    }
+
+    // Progress expects user_data to be *Response
+    #[allow(unused_variable)]
+    fn http_progress_fn(user_data: libc::uintptr_t, dltotal: libc::c_double,
+                        dlnow: libc::c_double, ultotal: libc::c_double,
+                        ulnow: libc::c_double) -> libc::size_t {
+        1
+    }
+}
+
+impl Request {
+    pub fn new(url: &str, method: Method) -> Request {
+        Request {
+            url: url.to_string(),
+            method: method,
+            headers: HashMap::new(),
+            redirects: RedirectPolicy::none(),
+            timeout: None,
+            connection_timeout: Some(0),
+            body: Empty,
+            resume_from: None,
+            auth: None,
+            tls: None,
+            accept_encoding: RawEncoding,
+            handler: RefCell::new(None),
+            url_error: None,
+        }
+    }
+
+    /// URL as it is requested, query included
+    pub fn url<'a>(&'a self) -> &'a str {
+        self.url.as_slice()
+    }
+
+    /// Adds query parameters, existing ones with the same keys
+    /// are dropped. Keys may repeat within `pairs`.
+    pub fn query(&mut self, pairs: &[(&str, &str)]) {
+        self.add_query(pairs, true);
+    }
+
+    /// Adds a query parameter after existing ones, even if
+    /// there are some with the same key
+    pub fn append_query(&mut self, key: &str, value: &str) {
+        self.add_query([(key, value)], false);
+    }
+
+    fn add_query(&mut self, pairs: &[(&str, &str)], replace: bool) {
+        let new_url = {
+            let (base, query, fragment) = split_query(self.url.as_slice());
+            let mut params: Vec<&str> = query.split('&').filter(|p| !p.is_empty()).collect();
+            if replace {
+                params.retain(|p| {
+                    let key = url::decode_component(p.splitn('=', 1).next().unwrap_or(""));
+                    !pairs.iter().any(|&(k, _)| k == key.as_slice())
+                });
+            }
+
+            let mut query = params.connect("&");
+            if !pairs.is_empty() {
+                if !query.is_empty() {
+                    query.push('&');
+                }
+                query.push_str(url::encode_pairs(pairs).as_slice());
+            }
+            if query.is_empty() {
+                format!("{}{}", base, fragment)
+            } else {
+                format!("{}?{}{}", base, query, fragment)
+            }
+        };
+        self.url = new_url;
+    }
+
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        self.headers.insert(name.to_string(), value.to_string());
+    }
+
+    fn has_header(&self, name: &str) -> bool {
+        self.headers.keys().any(|k| k.as_slice().eq_ignore_ascii_case(name))
+    }
+
+    /// Removes header regardless of its case
+    pub fn remove_header(&mut self, name: &str) {
+        let keys: Vec<String> = self.headers.keys()
+            .filter(|k| k.as_slice().eq_ignore_ascii_case(name))
+            .map(|k| k.clone())
+            .collect();
+        for k in keys.iter() {
+            self.headers.remove(k);
+        }
+    }
+
+    pub fn set_body(&mut self, body: Body) {
+        self.body = body;
+    }
+
+    /// Sets response body sink, e.g. `FileHandler` to download
+    /// straight to disk. Sink is used by a single `perform` only.
+    pub fn set_handler(&mut self, handler: Box<Handler+Send>) {
+        *self.handler.borrow_mut() = Some(handler);
+    }
+
+    /// Sets JSON body and `Content-Type`, asks for JSON
+    /// response unless there is `Accept` already
+    pub fn json<'a, T: Encodable<serialize::json::Encoder<'a>, IoError>>(&mut self, value: &T) {
+        self.body = json::to_body(value);
+        self.remove_header("Content-Type");
+        self.set_header("Content-Type", "application/json");
+        if !self.has_header("Accept") {
+            self.set_header("Accept", "application/json");
+        }
+    }
+
+    /// Sets multipart body, libcurl generates the
+    /// `Content-Type: multipart/form-data` header itself
+    pub fn set_form(&mut self, form: Form) {
+        self.body = Multipart(form);
+    }
+}
+
+impl Hop {
+    fn first(req: &Request) -> Hop {
+        Hop {
+            url: req.url.clone(),
+            method: req.method,
+            body: true,
+            credentials: true,
+            referer: None,
+            validators: None,
+        }
+    }
+
+    // Where `redirect` to `target` leads, None if it isn't followed
+    fn next(&self, req: &Request, redirect: &Redirect, target: &str) -> Option<Hop> {
+        let policy = &req.redirects;
+        if !policy.allows(redirect, self.url.as_slice(), target) {
+            return None;
+        }
+
+        let (method, body) = policy.next_method(self.method, redirect.status);
+        let body = self.body && body;
+        if body && !req.body.is_replayable() {
+            // Can't be sent again, so the redirect is the answer
+            return None;
+        }
+
+        // No referer from a secure page to a plain one
+        let downgrade = self.url.as_slice().starts_with("https:") && !target.starts_with("https:");
+        Some(Hop {
+            url: target.to_string(),
+            method: method,
+            body: body,
+            credentials: !policy.strip_credentials || redirect::same_origin(req.url.as_slice(), target),
+            referer: if policy.referer && !downgrade { Some(self.url.clone()) } else { None },
+            validators: None,
+        })
+    }
+}
+
+// Splits URL into the part before `?`, the query and the fragment with `#`
+fn split_query<'a>(url: &'a str) -> (&'a str, &'a str, &'a str) {
+    let (rest, fragment) = match url.find('#') {
+        Some(i) => (url.slice_to(i), url.slice_from(i)),
+        None => (url, "")
+    };
+    match rest.find('?') {
+        Some(i) => (rest.slice_to(i), rest.slice_from(i + 1), fragment),
+        None => (rest, "", fragment)
+    }
+}
+
+// Replaces cookies of `to` with the ones of `from`
+fn copy_cookies(from: &Curl, to: &Curl) {
+    // Listed first as both may use the same shared jar
+    let cookies = CookieJar::new(from).list();
+    let target = CookieJar::new(to);
+    let _ = target.clear();
+    for cookie in cookies.iter() {
+        let _ = target.add(cookie);
+    }
+}
+
+// Adds cookies of `from` to `to`, the ones `to` has are kept
+// unless `from` has a cookie with the same name, domain and path
+fn merge_cookies(from: &Curl, to: &Curl) {
+    let target = CookieJar::new(to);
+    for cookie in CookieJar::new(from).list().iter() {
+        let _ = target.add(cookie);
+    }
+}
+
+// Headers dropped when a redirect leaves the origin
+fn is_credential_header(name: &str) -> bool {
+    name.eq_ignore_ascii_case("Authorization") || name.eq_ignore_ascii_case("Cookie")
+}
+
+impl ConnectionInfo {
+    fn new() -> ConnectionInfo {
+        ConnectionInfo {
+            primary_ip: String::new(),
+            primary_port: 0,
+            local_ip: String::new(),
+            local_port: 0,
+            num_connects: 0,
+            connect_code: 0,
+            redirect_count: 0,
+            http_version: None,
+        }
+    }
+
+    fn from_session(session: &Curl) -> ConnectionInfo {
+        let long = |option| {
+            let val: Option<int> = session.getinfo(option);
+            val.unwrap_or(0)
+        };
+        let string = |option| {
+            let val: Option<String> = session.getinfo(option);
+            val.unwrap_or(String::new())
+        };
+
+        // CURL_HTTP_VERSION_* values
+        let version = match long(info::HTTP_VERSION) {
+            1 => Some(Http10),
+            2 => Some(Http11),
+            3 => Some(Http2),
+            30 => Some(Http3),
+            _ => None
+        };
+
+        ConnectionInfo {
+            primary_ip: string(info::PRIMARY_IP),
+            primary_port: long(info::PRIMARY_PORT) as u16,
+            local_ip: string(info::LOCAL_IP),
+            local_port: long(info::LOCAL_PORT) as u16,
+            num_connects: long(info::NUM_CONNECTS) as uint,
+            connect_code: long(info::HTTP_CONNECTCODE) as u16,
+            redirect_count: long(info::REDIRECT_COUNT) as uint,
+            http_version: version,
+        }
+    }
+
+    /// Whether an existing connection was used
+    pub fn is_reused(&self) -> bool {
+        self.num_connects == 0
+    }
+}
+
+impl Response {
+    fn new() -> Response {
+        Response {
+            status_code: 0,
+            url: "".to_string(),
+            headers: Headers::new(),
+            status_message: "".to_string(),
+            version: None,
+            interim: Vec::new(),
+            trailers: Headers::new(),
+            auth_offered: Vec::new(),
+            attempts: Vec::new(),
+            history: Vec::new(),
+            content_encoding: None,
+            compressed_size: None,
+            cache_status: None,
+            connection: ConnectionInfo::new(),
+            content_data: None
+        }
+    }
+
+    /// Response which wasn't received from a server,
+    /// e.g. made up by an interceptor
+    pub fn synthetic(status_code: u16, headers: Headers, body: Vec<u8>) -> Response {
+        let mut resp = Response::new();
+        resp.status_code = status_code;
+        resp.headers = headers;
+        resp.content_data = Some(box MemReader::new(body) as Box<Reader>);
+        resp
+    }
+
+    /// Decodes JSON body, `content_data` is consumed even on error
+    pub fn json<T: Decodable<serialize::json::Decoder, serialize::json::DecoderError>>(&mut self)
+                -> Result<T, JsonError> {
+        let body = match self.content_data.take() {
+            Some(mut reader) => match reader.read_to_end() {
+                Ok(body) => Some(body),
+                Err(e) => return Err(JsonError {
+                    kind: BodyReadError(e),
+                    status: self.status_code,
+                    excerpt: String::new(),
+                })
+            },
+            None => None
+        };
+        json::from_body(self.status_code, &self.headers, body)
+    }
+
+    // Takes the final head from parsed ones, the rest is interim
+    fn set_heads(&mut self, parser: HeadParser) {
+        let (mut heads, trailers) = parser.finish();
+        match heads.pop() {
+            Some(ResponseHead { status, headers }) => {
+                self.version = Some(status.version);
+                self.status_message = status.reason;
+                self.headers = headers;
+            },
+            None => ()
+        }
+        self.interim = heads;
+        self.trailers = trailers;
+        self.auth_offered = auth::offered_schemes(&self.headers);
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::{Client, Body, RedirectPolicy, Delete, Head};
+    use errors::CURLE_BAD_FUNCTION_ARGUMENT;
+    use mime::Form;
+    use std::io::MemReader;
+    use time;
+
+    #[test]
+    fn simple_get() {
+        let mut c = Client::new("http://baidu.com");
+        let mut req = c.new_get_request("/");
+
+        let resp = c.perform(&mut req).unwrap();
+        assert_eq!(resp.status_code, 200);
+        let content = resp.content_data.unwrap().read_to_string().unwrap();
+        assert!(content.as_slice().find_str("www.baidu.com").is_some());
+    }
+
+    #[test]
+    fn redirections() {
+        let mut c = Client::new("http://google.com");
+        let mut req = c.new_get_request("/");
+
+        let resp = c.perform(&mut req).unwrap();
+        assert_eq!(resp.status_code / 100, 3);
+
+        req.redirects = RedirectPolicy::limited(10);
+        let resp = c.perform(&mut req).unwrap();
+        assert_eq!(resp.status_code, 200);
+    }
+
+    #[test]
+    fn headers() {
+        let mut c = Client::new("http://google.com");
+        let mut req = c.new_get_request("/");
+        req.redirects = RedirectPolicy::limited(10);
+
+        let resp = c.perform(&mut req).unwrap();
+        assert_eq!(resp.status_code, 200);
+        assert!(resp.headers.len() > 0);
+
+        let ct = resp.headers.get("content-type").unwrap();
+        assert!(ct.starts_with("text/html"));
+        assert_eq!(resp.headers.mime_type(), Some("text/html".to_string()));
+    }
+
+    #[test]
+    fn interim_heads() {
+        let mut c = Client::new("http://httpbin.org/");
+        let mut req = c.new_get_request("redirect/1");
+        req.redirects = RedirectPolicy::limited(5);
+
+        // libcurl follows redirects of streamed requests, so
+        // their heads are interim ones of the final response
+        let resp = c.perform_streaming(req).unwrap();
+        assert_eq!(resp.status_code, 200);
+        assert_eq!(resp.interim.len(), 1);
+        assert_eq!(resp.interim[0].status.code, 302);
+        assert!(resp.interim[0].headers.location().is_some());
+        assert!(resp.version.is_some());
+    }
+
+    #[test]
+    fn rel_urls() {
+        let resolve = |base, rel| Client::get_rel_url(base, rel).unwrap();
+        assert_eq!(resolve("http://h/api", "users").as_slice(), "http://h/api/users");
+        assert_eq!(resolve("http://h/api/", "users?x=1").as_slice(), "http://h/api/users?x=1");
+        assert_eq!(resolve("http://h/api", "/users").as_slice(), "http://h/users");
+        assert_eq!(resolve("http://h/api", "https://o/x").as_slice(), "https://o/x");
+
+        let mut c = Client::new("not a url");
+        let mut req = c.new_get_request("users");
+        assert_eq!(c.perform(&mut req).err().unwrap().code, 3);
+    }
+
+    #[test]
+    fn query() {
+        let c = Client::new("http://example.com/");
+        let mut req = c.new_get_request("search?q=old&page=1#top");
+        req.query([("q", "a b&c"), ("tag", "x"), ("tag", "y")]);
+        assert_eq!(req.url(), "http://example.com/search?page=1&q=a+b%26c&tag=x&tag=y#top");
+
+        req.append_query("tag", "ü");
+        assert_eq!(req.url(), "http://example.com/search?page=1&q=a+b%26c&tag=x&tag=y&tag=%C3%BC#top");
+
+        req.query([("tag", "z")]);
+        assert_eq!(req.url(), "http://example.com/search?page=1&q=a+b%26c&tag=z#top");
+    }
+
+    #[test]
+    fn connection_reuse() {
+        let mut c = Client::new("http://httpbin.org/");
+        let mut req = c.new_get_request("get");
+
+        let first = c.perform(&mut req).unwrap().connection;
+        assert_eq!(first.num_connects, 1);
+        assert_eq!(first.primary_port, 80);
+        assert!(first.primary_ip.len() > 0 && first.local_port > 0);
+
+        let second = c.perform(&mut req).unwrap().connection;
+        assert!(second.is_reused());
+        assert_eq!(second.local_port, first.local_port);
+    }
+
+    #[test]
+    fn streaming() {
+        let mut c = Client::new("http://httpbin.org/");
+        let req = c.new_get_request("stream-bytes/1048576?chunk_size=4096");
+
+        let resp = c.perform_streaming(req).unwrap();
+        assert_eq!(resp.status_code, 200);
+        let body = resp.content_data.unwrap().read_to_end().unwrap();
+        assert_eq!(body.len(), 1048576);
+    }
+
+    #[test]
+    fn streaming_returns_at_head() {
+        let mut c = Client::new("http://httpbin.org/");
+        let req = c.new_get_request("drip?duration=3&numbytes=3&delay=0");
+        let started = time::precise_time_ns();
+        let resp = c.perform_streaming(req).unwrap();
+        assert_eq!(resp.status_code, 200);
+        assert!(time::precise_time_ns() - started < 2000000000);
+        assert_eq!(resp.content_data.unwrap().read_to_end().unwrap().len(), 3);
+
+        let req = c.new_get_request("status/204");
+        let resp = c.perform_streaming(req).unwrap();
+        assert_eq!(resp.status_code, 204);
+        assert_eq!(resp.content_data.unwrap().read_to_end().unwrap().len(), 0);
+
+        // Cookies are there before the body is read
+        let req = c.new_get_request("cookies/set?streamed=1");
+        let _resp = c.perform_streaming(req).unwrap();
+        assert!(c.cookie_jar().list().iter().any(|cookie| cookie.name.as_slice() == "streamed"));
+    }
+
+    #[test]
+    fn post_body() {
+        let mut c = Client::new("http://httpbin.org/");
+        let mut req = c.new_post_request("post");
+        req.set_body(Body::from_pairs([("a", "b c")]));
+
+        let resp = c.perform(&mut req).unwrap();
+        assert_eq!(resp.status_code, 200);
+        let content = resp.content_data.unwrap().read_to_string().unwrap();
+        assert!(content.as_slice().find_str("\"a\": \"b c\"").is_some());
+    }
+
+    #[test]
+    fn bodies_of_other_methods() {
+        let mut c = Client::new("http://httpbin.org/");
+        let mut req = c.new_get_request("anything");
+        req.set_header("X-Probe", "1");
+        req.set_body(Body::from_pairs([("a", "b")]));
+        let content = c.perform(&mut req).unwrap().content_data.unwrap().read_to_string().unwrap();
+        assert!(content.as_slice().contains("\"method\": \"GET\""));
+        assert!(content.as_slice().contains("\"a\": \"b\""));
+
+        // Headers of the previous request are not sent again
+        let mut req = c.new_get_request("anything");
+        req.method = Delete;
+        req.set_body(Body::from_reader(box MemReader::new(b"gone".to_vec()), None));
+        let content = c.perform(&mut req).unwrap().content_data.unwrap().read_to_string().unwrap();
+        assert!(content.as_slice().contains("\"method\": \"DELETE\""));
+        assert!(content.as_slice().contains("gone"));
+        assert!(!content.as_slice().contains("X-Probe"));
+
+        let mut req = c.new_get_request("anything");
+        req.method = Head;
+        req.set_body(Body::from_pairs([("a", "b")]));
+        assert_eq!(c.perform(&mut req).err().unwrap().code, CURLE_BAD_FUNCTION_ARGUMENT as uint);
+    }
+
+    #[test]
+    fn put_stream() {
+        let mut c = Client::new("http://httpbin.org/");
+        let mut req = c.new_put_request("put");
+        req.set_body(Body::from_reader(box MemReader::new(b"streamed".to_vec()), None));
+
+        let resp = c.perform(&mut req).unwrap();
+        assert_eq!(resp.status_code, 200);
+        let content = resp.content_data.unwrap().read_to_string().unwrap();
+        assert!(content.as_slice().find_str("streamed").is_some());
+    }
+
+    #[test]
+    fn put_form() {
+        let mut form = Form::new();
+        form.add_text("name", "value");
+
+        // httpbin answers 405 to anything but PUT here
+        let mut c = Client::new("http://httpbin.org/");
+        let mut req = c.new_put_request("put");
+        req.set_form(form);
+        let resp = c.perform(&mut req).unwrap();
+        assert_eq!(resp.status_code, 200);
+        let content = resp.content_data.unwrap().read_to_string().unwrap();
+        assert!(content.as_slice().contains("\"name\": \"value\""));
+    }
+}
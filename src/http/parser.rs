@@ -0,0 +1,389 @@
+use std::mem;
+use std::str;
+
+use super::headers::Headers;
+
+/// HTTP version from the status line
+#[deriving(Clone, Show, PartialEq)]
+pub enum Version {
+    Http10,
+    Http11,
+    Http2,
+    Http3,
+    /// Anything else which still looks like `HTTP/x[.y]`
+    OtherVersion(u8, u8),
+}
+
+#[deriving(Clone, Show, PartialEq)]
+pub struct StatusLine {
+    pub version: Version,
+    pub code: u16,
+    /// Reason phrase, empty for HTTP/2 and HTTP/3
+    pub reason: String,
+}
+
+/// Status line and headers of a single response
+#[deriving(Clone, Show, PartialEq)]
+pub struct ResponseHead {
+    pub status: StatusLine,
+    pub headers: Headers,
+}
+
+#[deriving(PartialEq, Show)]
+enum State {
+    // Waiting for a status line
+    Status,
+    // Reading header lines of the current head
+    Fields,
+    // Head is complete, only trailers or a new head may follow
+    Body,
+    // Reading trailer lines
+    Trailers,
+}
+
+/// Incremental parser of response heads as libcurl reports them
+/// to the header callback
+///
+/// Input may be split at any byte. Every status line starts a new
+/// head, so interim (1xx), proxy CONNECT and redirect responses are
+/// all recorded, the last one is the final response. Lines after the
+/// final head which are not status lines are chunked trailers.
+/// Malformed lines never stop the parser, they are reported by `errors`.
+pub struct HeadParser {
+    state: State,
+    line: Vec<u8>,
+    // Last field is kept until the next line as it might be folded
+    pending: Option<(String, Vec<u8>)>,
+    heads: Vec<ResponseHead>,
+    trailers: Headers,
+    errors: Vec<String>,
+}
+
+impl HeadParser {
+    pub fn new() -> HeadParser {
+        HeadParser {
+            state: Status,
+            line: Vec::new(),
+            pending: None,
+            heads: Vec::new(),
+            trailers: Headers::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn feed(&mut self, data: &[u8]) {
+        for &b in data.iter() {
+            if b == b'\n' {
+                let mut line = mem::replace(&mut self.line, Vec::new());
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                self.on_line(line.as_slice());
+            } else {
+                self.line.push(b);
+            }
+        }
+    }
+
+    /// Whether the last head is complete
+    pub fn is_complete(&self) -> bool {
+        self.state == Body || self.state == Trailers
+    }
+
+    /// Head of the most recent response, possibly incomplete
+    pub fn last<'a>(&'a self) -> Option<&'a ResponseHead> {
+        self.heads.last()
+    }
+
+    pub fn heads<'a>(&'a self) -> &'a [ResponseHead] {
+        self.heads.as_slice()
+    }
+
+    pub fn trailers<'a>(&'a self) -> &'a Headers {
+        &self.trailers
+    }
+
+    pub fn errors<'a>(&'a self) -> &'a [String] {
+        self.errors.as_slice()
+    }
+
+    /// Completes parsing, returns all heads (final one is the last)
+    /// and trailers
+    pub fn finish(mut self) -> (Vec<ResponseHead>, Headers) {
+        // Input might lack the final line break
+        if self.line.len() > 0 {
+            let line = mem::replace(&mut self.line, Vec::new());
+            self.on_line(line.as_slice());
+        }
+        self.flush_pending();
+        (self.heads, self.trailers)
+    }
+
+    fn on_line(&mut self, line: &[u8]) {
+        let folded = line.len() > 0 && (line[0] == b' ' || line[0] == b'\t');
+
+        match self.state {
+            Status | Body if line.len() == 0 => (),
+            Status | Body | Trailers if is_status_line(line) => {
+                self.flush_pending();
+                match parse_status_line(line) {
+                    Some(status) => {
+                        self.heads.push(ResponseHead { status: status, headers: Headers::new() });
+                        // Trailers of a previous response don't apply anymore
+                        self.trailers.clear();
+                        self.state = Fields;
+                    },
+                    None => {
+                        self.error("Malformed status line", line);
+                        self.state = Status;
+                    }
+                }
+            },
+            Status => self.error("Expected status line", line),
+            Fields | Trailers if line.len() == 0 => {
+                self.flush_pending();
+                self.state = Body;
+            },
+            Fields | Trailers if folded => {
+                // obs-fold, replaced with a single space
+                match self.pending {
+                    Some((_, ref mut value)) => {
+                        value.push(b' ');
+                        value.push_all(trim(line));
+                    },
+                    None => self.error("Continuation without a field", line),
+                }
+            },
+            Fields | Trailers | Body => {
+                self.flush_pending();
+                if self.state == Body {
+                    self.state = Trailers;
+                }
+                match parse_field(line) {
+                    Some((name, value)) => self.pending = Some((name, value.to_vec())),
+                    None => self.error("Malformed header field", line),
+                }
+            },
+        }
+    }
+
+    fn flush_pending(&mut self) {
+        let (name, value) = match self.pending.take() {
+            Some(field) => field,
+            None => return
+        };
+        let value = String::from_utf8_lossy(value.as_slice()).into_string();
+
+        match self.state {
+            Trailers => self.trailers.append(name.as_slice(), value.as_slice()),
+            _ => match self.heads.last_mut() {
+                Some(head) => head.headers.append(name.as_slice(), value.as_slice()),
+                None => ()
+            }
+        }
+    }
+
+    fn error(&mut self, what: &str, line: &[u8]) {
+        let msg = format!("{}: {}", what, String::from_utf8_lossy(line));
+        debug!("{}", msg);
+        self.errors.push(msg);
+    }
+}
+
+fn is_status_line(line: &[u8]) -> bool {
+    line.len() >= 5 && line.slice_to(5) == b"HTTP/"
+}
+
+// HTTP/1.1 200 OK, HTTP/2 200, reason may be missing or empty
+fn parse_status_line(line: &[u8]) -> Option<StatusLine> {
+    let rest = line.slice_from(5);
+
+    let major = match rest.get(0) {
+        Some(&c) if is_digit(c) => c - b'0',
+        _ => return None
+    };
+    let (minor, rest) = match (rest.get(1), rest.get(2)) {
+        (Some(&b'.'), Some(&c)) if is_digit(c) => (Some(c - b'0'), rest.slice_from(3)),
+        _ => (None, rest.slice_from(1)),
+    };
+
+    // Single SP, then exactly three digits
+    if rest.len() < 4 || rest[0] != b' ' || !rest.slice(1, 4).iter().all(|&c| is_digit(c)) {
+        return None;
+    }
+    let code = rest.slice(1, 4).iter().fold(0u16, |acc, &c| acc * 10 + (c - b'0') as u16);
+
+    let reason = match rest.get(4) {
+        None => Vec::new(),
+        Some(&b' ') => trim(rest.slice_from(5)).to_vec(),
+        Some(_) => return None
+    };
+
+    let version = match (major, minor) {
+        (1, Some(0)) => Http10,
+        (1, Some(1)) => Http11,
+        (2, _) => Http2,
+        (3, _) => Http3,
+        (major, minor) => OtherVersion(major, minor.unwrap_or(0)),
+    };
+
+    Some(StatusLine {
+        version: version,
+        code: code,
+        reason: String::from_utf8_lossy(reason.as_slice()).into_string(),
+    })
+}
+
+// field-name ":" OWS field-value OWS, name has to be a token
+fn parse_field<'a>(line: &'a [u8]) -> Option<(String, &'a [u8])> {
+    let pos = match line.iter().position(|&c| c == b':') {
+        Some(pos) if pos > 0 => pos,
+        _ => return None
+    };
+    let name = line.slice_to(pos);
+    if !name.iter().all(|&c| is_token(c)) {
+        return None;
+    }
+
+    // Token chars are ASCII
+    let name = str::from_utf8(name).unwrap().to_string();
+    Some((name, trim(line.slice_from(pos + 1))))
+}
+
+fn trim<'a>(s: &'a [u8]) -> &'a [u8] {
+    let is_ws = |c: &u8| *c == b' ' || *c == b'\t' || *c == b'\r';
+    let start = s.iter().position(|c| !is_ws(c)).unwrap_or(s.len());
+    let end = s.iter().rposition(|c| !is_ws(c)).map(|p| p + 1).unwrap_or(start);
+    s.slice(start, end)
+}
+
+fn is_digit(c: u8) -> bool {
+    c >= b'0' && c <= b'9'
+}
+
+// RFC 7230 tchar
+fn is_token(c: u8) -> bool {
+    match c as char {
+        'a'...'z' | 'A'...'Z' | '0'...'9' |
+        '!' | '#' | '$' | '%' | '&' | '\'' | '*' | '+' |
+        '-' | '.' | '^' | '_' | '`' | '|' | '~' => true,
+        _ => false
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::{HeadParser, Http11, Http2, OtherVersion, parse_status_line};
+    use std::rand::{task_rng, Rng};
+
+    static RESPONSE: &'static [u8] = b"HTTP/1.1 100 Continue\r\n\r\n\
+HTTP/1.1 103 Early Hints\r\nLink: </style.css>; rel=preload\r\n\r\n\
+HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nX-Empty:\r\n\
+X-Folded: a\r\n  b\r\nSet-Cookie: a=1\r\nSet-Cookie: b=2\r\n\
+Transfer-Encoding: chunked\r\n\r\n\
+X-Checksum: abc\r\n\r\n";
+
+    fn check_response(parser: HeadParser) {
+        let (heads, trailers) = parser.finish();
+        assert_eq!(heads.len(), 3);
+        assert_eq!(heads[0].status.code, 100);
+        assert_eq!(heads[1].status.code, 103);
+        assert_eq!(heads[1].headers.get("link"), Some("</style.css>; rel=preload"));
+
+        let last = &heads[2];
+        assert_eq!(last.status.version, Http11);
+        assert_eq!(last.status.code, 200);
+        assert_eq!(last.status.reason.as_slice(), "OK");
+        assert_eq!(last.headers.get("X-Empty"), Some(""));
+        assert_eq!(last.headers.get("X-Folded"), Some("a b"));
+        assert_eq!(last.headers.get_all("Set-Cookie"), vec!("a=1", "b=2"));
+        assert_eq!(trailers.get("X-Checksum"), Some("abc"));
+    }
+
+    #[test]
+    fn whole() {
+        let mut p = HeadParser::new();
+        p.feed(RESPONSE);
+        assert!(p.is_complete());
+        assert!(p.errors().is_empty());
+        check_response(p);
+    }
+
+    #[test]
+    fn split_anywhere() {
+        for i in range(0, RESPONSE.len()) {
+            let mut p = HeadParser::new();
+            p.feed(RESPONSE.slice_to(i));
+            p.feed(RESPONSE.slice_from(i));
+            check_response(p);
+        }
+    }
+
+    #[test]
+    fn status_lines() {
+        let s = parse_status_line(b"HTTP/2 200").unwrap();
+        assert_eq!((s.version, s.code, s.reason.as_slice()), (Http2, 200, ""));
+        let s = parse_status_line(b"HTTP/3 404 ").unwrap();
+        assert_eq!(s.code, 404);
+        let s = parse_status_line(b"HTTP/1.2 500 Internal \xff Error").unwrap();
+        assert_eq!(s.version, OtherVersion(1, 2));
+        assert_eq!(s.reason.as_slice(), "Internal � Error");
+
+        assert!(parse_status_line(b"HTTP/1.1 20 OK").is_none());
+        assert!(parse_status_line(b"HTTP/1.1 2000 OK").is_none());
+        assert!(parse_status_line(b"HTTP/x 200 OK").is_none());
+    }
+
+    #[test]
+    fn proxy_connect() {
+        let mut p = HeadParser::new();
+        p.feed(b"HTTP/1.1 200 Connection established\r\n\r\nHTTP/2 204\r\nserver: x\r\n\r\n");
+        let (heads, _) = p.finish();
+        assert_eq!(heads.len(), 2);
+        assert_eq!(heads[1].status.version, Http2);
+        assert_eq!(heads[1].headers.get("Server"), Some("x"));
+    }
+
+    #[test]
+    fn malformed() {
+        let mut p = HeadParser::new();
+        p.feed(b"garbage\r\nHTTP/1.1 200 OK\r\nno colon\r\n: no name\r\nbad name: x\r\n ok\r\n\r\n");
+        // Each malformed line is reported once, the continuation has
+        // no field to continue as the previous one was dropped
+        let expected = ["Expected status line: garbage",
+                        "Malformed header field: no colon",
+                        "Malformed header field: : no name",
+                        "Malformed header field: bad name: x",
+                        "Continuation without a field:  ok"];
+        assert_eq!(p.errors().len(), expected.len());
+        for (error, expected) in p.errors().iter().zip(expected.iter()) {
+            assert_eq!(error.as_slice(), *expected);
+        }
+        let (heads, _) = p.finish();
+        assert_eq!(heads[0].headers.len(), 0);
+    }
+
+    // Random input must never panic
+    #[test]
+    fn fuzz() {
+        let mut rng = task_rng();
+        let alphabet = b"HTP/1.2 0:\r\n\t ab\xff";
+        for _ in range(0u, 2000) {
+            let mut input = RESPONSE.to_vec();
+            for _ in range(0u, rng.gen_range(1u, 20)) {
+                let pos = rng.gen_range(0, input.len());
+                match rng.gen_range(0u, 3) {
+                    0 => { input.remove(pos); },
+                    1 => input.insert(pos, *rng.choose(alphabet).unwrap()),
+                    _ => *input.get_mut(pos) = rng.gen(),
+                }
+            }
+            let split = rng.gen_range(0, input.len() + 1);
+            let mut p = HeadParser::new();
+            p.feed(input.slice_to(split));
+            p.feed(input.slice_from(split));
+            let _ = p.finish();
+        }
+    }
+}
@@ -0,0 +1,332 @@
+use std::ascii::StrAsciiExt;
+use std::cmp;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use share::{Share, LOCK_DATA_DNS, LOCK_DATA_SSL_SESSION, LOCK_DATA_COOKIE};
+use url::Url;
+use super::{Client, CurlError, Method, Get, Post, Put, Patch, Request, Response};
+
+/// Concurrency caps of a `Pool`, 0 is no limit
+#[deriving(Clone, Show, PartialEq)]
+pub struct PoolLimits {
+    /// Transfers in flight at once
+    pub max_total: uint,
+    /// Transfers in flight to one host and port at once
+    pub max_per_host: uint,
+}
+
+impl PoolLimits {
+    pub fn new(max_total: uint, max_per_host: uint) -> PoolLimits {
+        PoolLimits {
+            max_total: max_total,
+            max_per_host: max_per_host,
+        }
+    }
+}
+
+/// Snapshot of pool usage
+#[deriving(Clone, Show, PartialEq)]
+pub struct PoolStats {
+    /// Clients waiting in the pool
+    pub idle: uint,
+    /// Clients checked out
+    pub active: uint,
+    /// Threads waiting for a free slot
+    pub waiting: uint,
+    /// Most clients checked out at once
+    pub peak_active: uint,
+    /// Clients created so far
+    pub created: uint,
+    pub checkouts: u64,
+    /// Checkouts which had to wait for a free slot
+    pub waited: u64,
+    /// Transfers by `Pool::perform` which opened a connection
+    pub new_connections: u64,
+    /// Transfers by `Pool::perform` which reused a kept-alive connection
+    pub reused_connections: u64,
+}
+
+struct State {
+    // By host key, a client keeps connections to the host alive
+    idle: HashMap<String, Vec<Client>>,
+    per_host: HashMap<String, uint>,
+    stats: PoolStats,
+}
+
+// Fields are dropped in order, clients have to go before the share
+struct Inner {
+    state: Mutex<State>,
+    // Only constructs requests, never performs
+    template: Mutex<Client>,
+    share: Share,
+    base_url: String,
+    limits: PoolLimits,
+    setup: fn(&mut Client),
+}
+
+/// `Client` which is shared by many threads
+///
+/// Every transfer checks out a client from the pool, so transfers
+/// run in parallel up to the limits. Clients share DNS cache, TLS
+/// sessions and cookies. Connections are not shared, as libcurl
+/// doesn't support that between threads: each client keeps its own
+/// alive, and a checkout prefers an idle client which was last
+/// used for the same host.
+///
+/// Clones refer to the same pool. Responses of `perform_streaming`
+/// must be dropped before the last clone.
+#[deriving(Clone)]
+pub struct Pool {
+    inner: Arc<Inner>,
+}
+
+/// Client checked out of a `Pool`, returned into it on drop
+pub struct PooledClient {
+    client: Option<Client>,
+    host: String,
+    inner: Arc<Inner>,
+}
+
+fn no_setup(_: &mut Client) {
+}
+
+// Lower case host and port, requests to an invalid URL fail
+// anyway so they share a slot
+fn host_key(url: &str) -> String {
+    match Url::parse(url) {
+        Ok(u) => {
+            let host = u.host().unwrap_or(String::new()).to_ascii_lower();
+            match u.port() {
+                Some(port) => format!("{}:{}", host, port),
+                None => host
+            }
+        },
+        Err(_) => String::new()
+    }
+}
+
+// Client last used for `host`, or any idle one
+fn take_idle(idle: &mut HashMap<String, Vec<Client>>, host: &String) -> Option<Client> {
+    match idle.find_mut(host).and_then(|list| list.pop()) {
+        Some(client) => return Some(client),
+        None => ()
+    }
+    for (_, list) in idle.iter_mut() {
+        match list.pop() {
+            Some(client) => return Some(client),
+            None => ()
+        }
+    }
+    None
+}
+
+impl Pool {
+    /// Pool of clients with a base URL, see `Client::new`
+    pub fn new(base_url: &str, limits: PoolLimits) -> Pool {
+        Pool::with_setup(base_url, limits, no_setup)
+    }
+
+    /// `setup` configures every client the pool creates,
+    /// e.g. sets authentication, proxy or retry policy
+    pub fn with_setup(base_url: &str, limits: PoolLimits, setup: fn(&mut Client)) -> Pool {
+        let share = Share::new();
+        for &data in [LOCK_DATA_DNS, LOCK_DATA_SSL_SESSION, LOCK_DATA_COOKIE].iter() {
+            share.share(data);
+        }
+
+        let mut template = Client::new(base_url);
+        setup(&mut template);
+
+        Pool {
+            inner: Arc::new(Inner {
+                state: Mutex::new(State {
+                    idle: HashMap::new(),
+                    per_host: HashMap::new(),
+                    stats: PoolStats {
+                        idle: 0,
+                        active: 0,
+                        waiting: 0,
+                        peak_active: 0,
+                        created: 0,
+                        checkouts: 0,
+                        waited: 0,
+                        new_connections: 0,
+                        reused_connections: 0,
+                    },
+                }),
+                template: Mutex::new(template),
+                share: share,
+                base_url: base_url.to_string(),
+                limits: limits,
+                setup: setup,
+            })
+        }
+    }
+
+    pub fn new_get_request(&self, rel_url: &str) -> Request {
+        self.new_request(rel_url, Get)
+    }
+
+    pub fn new_post_request(&self, rel_url: &str) -> Request {
+        self.new_request(rel_url, Post)
+    }
+
+    pub fn new_put_request(&self, rel_url: &str) -> Request {
+        self.new_request(rel_url, Put)
+    }
+
+    pub fn new_patch_request(&self, rel_url: &str) -> Request {
+        self.new_request(rel_url, Patch)
+    }
+
+    fn new_request(&self, rel_url: &str, method: Method) -> Request {
+        self.inner.template.lock().new_request(rel_url, method)
+    }
+
+    /// Waits until the limits allow a transfer to `url`
+    /// and takes an idle client or creates one
+    pub fn checkout(&self, url: &str) -> PooledClient {
+        let host = host_key(url);
+        let limits = &self.inner.limits;
+        let mut state = self.inner.state.lock();
+
+        let mut waited = false;
+        loop {
+            let host_active = state.per_host.find(&host).map(|&n| n).unwrap_or(0);
+            let total_full = limits.max_total > 0 && state.stats.active >= limits.max_total;
+            let host_full = limits.max_per_host > 0 && host_active >= limits.max_per_host;
+            if !total_full && !host_full {
+                break;
+            }
+            if !waited {
+                waited = true;
+                state.stats.waited += 1;
+            }
+            state.stats.waiting += 1;
+            state.cond.wait();
+            state.stats.waiting -= 1;
+        }
+
+        let client = match take_idle(&mut state.idle, &host) {
+            Some(client) => client,
+            None => {
+                let mut client = Client::new(self.inner.base_url.as_slice());
+                self.inner.share.attach(&client.session);
+                (self.inner.setup)(&mut client);
+                state.stats.created += 1;
+                client
+            }
+        };
+        state.stats.checkouts += 1;
+        state.stats.active += 1;
+        state.stats.peak_active = cmp::max(state.stats.peak_active, state.stats.active);
+        let host_active = state.per_host.find(&host).map(|&n| n).unwrap_or(0);
+        state.per_host.insert(host.clone(), host_active + 1);
+
+        PooledClient {
+            client: Some(client),
+            host: host,
+            inner: self.inner.clone(),
+        }
+    }
+
+    /// Performs the request with a checked out client
+    pub fn perform(&self, req: &mut Request) -> Result<Response, CurlError> {
+        let result = self.checkout(req.url()).perform(req);
+        match result {
+            Ok(ref resp) => {
+                let mut state = self.inner.state.lock();
+                if resp.connection.is_reused() {
+                    state.stats.reused_connections += 1;
+                } else {
+                    state.stats.new_connections += 1;
+                }
+            },
+            Err(_) => ()
+        }
+        result
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        let state = self.inner.state.lock();
+        let mut stats = state.stats.clone();
+        stats.idle = state.idle.values().fold(0, |n, list| n + list.len());
+        stats
+    }
+
+    /// Drops idle clients along with their kept-alive connections
+    pub fn shrink(&self) {
+        self.inner.state.lock().idle.clear();
+    }
+}
+
+impl Deref<Client> for PooledClient {
+    fn deref<'a>(&'a self) -> &'a Client {
+        self.client.as_ref().unwrap()
+    }
+}
+
+impl DerefMut<Client> for PooledClient {
+    fn deref_mut<'a>(&'a mut self) -> &'a mut Client {
+        self.client.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledClient {
+    fn drop(&mut self) {
+        let mut state = self.inner.state.lock();
+        if !state.idle.contains_key(&self.host) {
+            state.idle.insert(self.host.clone(), Vec::new());
+        }
+        state.idle.find_mut(&self.host).unwrap().push(self.client.take().unwrap());
+        state.stats.active -= 1;
+        match state.per_host.find(&self.host).map(|&n| n) {
+            Some(1) => { state.per_host.remove(&self.host); },
+            Some(n) => { state.per_host.insert(self.host.clone(), n - 1); },
+            None => ()
+        }
+        state.cond.broadcast();
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::{Pool, PoolLimits, host_key};
+
+    fn check_send_sync<T: Send+Sync>() {
+    }
+
+    #[test]
+    fn hosts() {
+        assert_eq!(host_key("http://Example.com/a").as_slice(), "example.com");
+        assert_eq!(host_key("http://example.com:8080/").as_slice(), "example.com:8080");
+        check_send_sync::<Pool>();
+    }
+
+    #[test]
+    fn threads() {
+        let pool = Pool::new("http://httpbin.org/", PoolLimits::new(2, 2));
+        let (tx, rx) = channel();
+        for _ in range(0u, 4) {
+            let (pool, tx) = (pool.clone(), tx.clone());
+            spawn(proc() {
+                let mut req = pool.new_get_request("delay/1");
+                let resp = pool.perform(&mut req).unwrap();
+                tx.send(resp.status_code);
+            });
+        }
+        for _ in range(0u, 4) {
+            assert_eq!(rx.recv(), 200);
+        }
+
+        let stats = pool.stats();
+        assert_eq!(stats.checkouts, 4);
+        assert_eq!(stats.peak_active, 2);
+        assert_eq!(stats.created, 2);
+        assert_eq!((stats.active, stats.idle), (0, 2));
+        assert!(stats.waited >= 2);
+        assert!(stats.reused_connections >= 2);
+    }
+}
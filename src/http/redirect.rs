@@ -0,0 +1,258 @@
+use libc::c_long;
+use std::ascii::StrAsciiExt;
+
+use easy::Curl;
+use opt;
+use url::Url;
+use super::{Method, Get, Head, Post, Headers};
+
+// CURL_REDIR_POST_* bits
+static REDIR_POST_301: c_long = 1;
+static REDIR_POST_302: c_long = 2;
+static REDIR_POST_303: c_long = 4;
+
+// CURLPROTO_* bits
+static PROTO_HTTP: c_long = 1 << 0;
+static PROTO_HTTPS: c_long = 1 << 1;
+
+/// Redirect response which was followed
+#[deriving(Clone, Show, PartialEq)]
+pub struct Redirect {
+    pub url: String,
+    pub status: u16,
+    pub headers: Headers,
+}
+
+/// Decides whether a particular redirect is followed
+pub trait RedirectFilter {
+    /// `redirect` is the response which points to `target`
+    fn follow(&self, redirect: &Redirect, target: &str) -> bool;
+}
+
+/// Which redirects `Client::perform` follows and how
+///
+/// Redirect which is not followed is returned as the response,
+/// going over `max_redirects` is an error.
+pub struct RedirectPolicy {
+    /// Hops followed at most, 0 doesn't follow at all
+    pub max_redirects: uint,
+    /// Only follow to the host of the previous hop
+    pub same_host: bool,
+    /// Statuses out of 301, 302 and 303 which keep POST as it is,
+    /// otherwise it turns into GET without body like browsers do.
+    /// 303 turns any other method except HEAD into GET as well,
+    /// 307 and 308 never change it.
+    pub keep_post: Vec<u16>,
+    /// Drop `Authorization` and `Cookie` headers and `Request::auth`
+    /// once scheme, host or port differ from the requested URL
+    pub strip_credentials: bool,
+    /// Send URL of the previous hop as `Referer`
+    pub referer: bool,
+    filter: Option<Box<RedirectFilter+Send>>,
+}
+
+impl RedirectPolicy {
+    /// Redirect is returned as the response
+    pub fn none() -> RedirectPolicy {
+        RedirectPolicy::limited(0)
+    }
+
+    /// Follows up to `max_redirects` hops anywhere
+    pub fn limited(max_redirects: uint) -> RedirectPolicy {
+        RedirectPolicy {
+            max_redirects: max_redirects,
+            same_host: false,
+            keep_post: Vec::new(),
+            strip_credentials: true,
+            referer: false,
+            filter: None,
+        }
+    }
+
+    /// Follows up to `max_redirects` hops within the same host
+    pub fn same_host(max_redirects: uint) -> RedirectPolicy {
+        let mut policy = RedirectPolicy::limited(max_redirects);
+        policy.same_host = true;
+        policy
+    }
+
+    /// Filter is asked about every hop which the policy allows
+    pub fn set_filter(&mut self, filter: Box<RedirectFilter+Send>) {
+        self.filter = Some(filter);
+    }
+
+    pub fn is_redirect(status: u16) -> bool {
+        [301, 302, 303, 307, 308].contains(&status)
+    }
+
+    /// Whether redirect from `from` to `target` is followed,
+    /// the count of hops is not checked
+    pub fn allows(&self, redirect: &Redirect, from: &str, target: &str) -> bool {
+        let target_url = match Url::parse(target) {
+            Ok(u) => u,
+            Err(_) => return false
+        };
+        let scheme = target_url.scheme().unwrap_or(String::new()).to_ascii_lower();
+        if scheme.as_slice() != "http" && scheme.as_slice() != "https" {
+            return false;
+        }
+        if self.same_host && !same_host(from, target) {
+            return false;
+        }
+        match self.filter {
+            Some(ref filter) => filter.follow(redirect, target),
+            None => true
+        }
+    }
+
+    /// Method of the next hop and whether the body is sent again
+    pub fn next_method(&self, method: Method, status: u16) -> (Method, bool) {
+        match (method, status) {
+            (m, 307) | (m, 308) => (m, true),
+            (Get, _) => (Get, false),
+            (Head, _) => (Head, false),
+            (Post, s) if self.keep_post.contains(&s) => (Post, true),
+            (Post, _) | (_, 303) => (Get, false),
+            (m, _) => (m, true),
+        }
+    }
+
+    /// Whether libcurl is able to follow redirects the same way
+    pub fn is_delegable(&self) -> bool {
+        !self.same_host && self.filter.is_none()
+    }
+
+    // Lets libcurl follow redirects, used by `perform_streaming`
+    pub fn apply(&self, session: &Curl) {
+        session.setopt(opt::FOLLOWLOCATION, self.max_redirects > 0);
+        session.setopt(opt::MAXREDIRS, self.max_redirects as int);
+        session.setopt(opt::REDIR_PROTOCOLS, (PROTO_HTTP | PROTO_HTTPS) as int);
+        session.setopt(opt::AUTOREFERER, self.referer);
+        session.setopt(opt::UNRESTRICTED_AUTH, !self.strip_credentials);
+
+        let mut post = 0;
+        for &(status, bit) in [(301, REDIR_POST_301), (302, REDIR_POST_302), (303, REDIR_POST_303)].iter() {
+            if self.keep_post.contains(&status) {
+                post |= bit;
+            }
+        }
+        session.setopt(opt::POSTREDIR, post as int);
+    }
+}
+
+// Lower case host and port with the scheme default
+fn host_port(url: &str) -> Option<(String, Option<u16>)> {
+    Url::parse(url).ok().map(|u| (u.host().unwrap_or(String::new()).to_ascii_lower(), u.port()))
+}
+
+/// Whether both URLs point to the same host, port doesn't matter
+pub fn same_host(a: &str, b: &str) -> bool {
+    match (host_port(a), host_port(b)) {
+        (Some((a, _)), Some((b, _))) => a == b,
+        _ => false
+    }
+}
+
+/// Whether scheme, host and port of both URLs match
+pub fn same_origin(a: &str, b: &str) -> bool {
+    let scheme = |url: &str| Url::parse(url).ok().and_then(|u| u.scheme()).map(|s| s.to_ascii_lower());
+    scheme(a) == scheme(b) && host_port(a).is_some() && host_port(a) == host_port(b)
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::{Redirect, RedirectFilter, RedirectPolicy, same_origin};
+    use http::{Client, Headers, Get, Post, Put, Head};
+
+    struct NoAbsolute;
+
+    impl RedirectFilter for NoAbsolute {
+        fn follow(&self, _: &Redirect, target: &str) -> bool {
+            !target.contains("absolute")
+        }
+    }
+
+    #[test]
+    fn methods() {
+        let mut p = RedirectPolicy::limited(5);
+        let is_get = |(m, body)| match m { Get => !body, _ => false };
+        assert!(is_get(p.next_method(Post, 301)));
+        assert!(is_get(p.next_method(Put, 303)));
+        assert!(match p.next_method(Put, 302) { (Put, true) => true, _ => false });
+        assert!(match p.next_method(Head, 303) { (Head, false) => true, _ => false });
+        assert!(match p.next_method(Post, 307) { (Post, true) => true, _ => false });
+
+        p.keep_post.push(302);
+        assert!(match p.next_method(Post, 302) { (Post, true) => true, _ => false });
+    }
+
+    #[test]
+    fn origins() {
+        assert!(same_origin("http://Example.com/a", "http://example.com:80/b"));
+        assert!(!same_origin("http://example.com/", "https://example.com/"));
+        assert!(!same_origin("http://example.com/", "http://example.com:8080/"));
+
+        let p = RedirectPolicy::same_host(5);
+        let r = Redirect { url: "http://a.com/".to_string(), status: 302, headers: Headers::new() };
+        assert!(p.allows(&r, "http://a.com/", "https://a.com/x"));
+        assert!(!p.allows(&r, "http://a.com/", "http://b.com/"));
+        assert!(!RedirectPolicy::limited(5).allows(&r, "http://a.com/", "ftp://a.com/"));
+    }
+
+    #[test]
+    fn history() {
+        let mut c = Client::new("http://httpbin.org/");
+        let mut req = c.new_get_request("redirect/2");
+        req.redirects = RedirectPolicy::limited(5);
+
+        let resp = c.perform(&mut req).unwrap();
+        assert_eq!(resp.status_code, 200);
+        assert_eq!(resp.url.as_slice(), "http://httpbin.org/get");
+        assert_eq!(resp.history.len(), 2);
+        assert_eq!(resp.history[0].url.as_slice(), "http://httpbin.org/redirect/2");
+        assert_eq!(resp.history[0].status, 302);
+        assert!(resp.history[1].headers.location().is_some());
+
+        req.redirects = RedirectPolicy::limited(1);
+        assert!(c.perform(&mut req).is_err());
+
+        req.redirects = RedirectPolicy::none();
+        let resp = c.perform(&mut req).unwrap();
+        assert_eq!(resp.status_code, 302);
+        assert!(resp.history.is_empty());
+    }
+
+    #[test]
+    fn filtered() {
+        let mut c = Client::new("http://httpbin.org/");
+        let mut req = c.new_get_request("absolute-redirect/1");
+        req.redirects = RedirectPolicy::limited(5);
+        req.redirects.set_filter(box NoAbsolute);
+
+        // Filter sees the target, not the redirect itself
+        assert_eq!(c.perform(&mut req).unwrap().status_code, 200);
+
+        let mut req = c.new_get_request("redirect-to?url=/absolute-redirect/1");
+        req.redirects = RedirectPolicy::limited(5);
+        req.redirects.set_filter(box NoAbsolute);
+        let resp = c.perform(&mut req).unwrap();
+        assert_eq!(resp.status_code, 302);
+        assert!(resp.history.is_empty());
+    }
+
+    #[test]
+    fn cross_origin_credentials() {
+        let mut c = Client::new("http://httpbin.org/");
+        let mut req = c.new_get_request("redirect-to?url=https%3A%2F%2Fhttpbin.org%2Fheaders");
+        req.set_header("Authorization", "Bearer secret");
+        req.redirects = RedirectPolicy::limited(5);
+
+        let content = c.perform(&mut req).unwrap().content_data.unwrap().read_to_string().unwrap();
+        assert!(content.as_slice().find_str("secret").is_none());
+
+        req.redirects.strip_credentials = false;
+        let content = c.perform(&mut req).unwrap().content_data.unwrap().read_to_string().unwrap();
+        assert!(content.as_slice().find_str("secret").is_some());
+    }
+}
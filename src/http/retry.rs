@@ -0,0 +1,205 @@
+use std::cmp;
+use std::rand::random;
+use time;
+
+use errors::{CURLE_COULDNT_RESOLVE_HOST, CURLE_COULDNT_CONNECT, CURLE_OPERATION_TIMEDOUT,
+             CURLE_SEND_ERROR, CURLE_RECV_ERROR, CURLE_GOT_NOTHING, CURLE_PARTIAL_FILE};
+use super::{Method, Get, Head, Put, Delete, Custom, Headers};
+use super::headers::parse_http_date;
+
+/// When and how `Client::perform` repeats failed requests
+///
+/// A request is repeated if it is idempotent (or `non_idempotent`
+/// is set), its body can be sent again and either the transfer
+/// failed with one of `errors` before any body was received, or
+/// the response status is one of `statuses`.
+#[deriving(Clone, Show)]
+pub struct RetryPolicy {
+    /// Attempts in total, including the first one
+    pub max_attempts: uint,
+    /// Retryable `CURLcode`s
+    pub errors: Vec<uint>,
+    /// Retryable HTTP statuses
+    pub statuses: Vec<u16>,
+    /// Retry POST and PATCH as well
+    pub non_idempotent: bool,
+    /// Delay before the first retry, doubled by `multiplier` every time
+    pub initial_delay_ms: u64,
+    pub max_delay_ms: u64,
+    pub multiplier: f64,
+    /// Delay is randomized by that fraction in both directions
+    pub jitter: f64,
+    /// Wait as long as `Retry-After` tells, but no longer
+    /// than `max_delay_ms` and up to the deadline
+    pub respect_retry_after: bool,
+    /// No retries start after that much time since the first attempt,
+    /// redirects included
+    pub deadline_ms: Option<u64>,
+}
+
+/// Attempt made by `Client::perform` under a retry policy,
+/// repeating a request with credentials is not an attempt
+#[deriving(Clone, Show, PartialEq)]
+pub struct RetryAttempt {
+    /// Response status, if there was a response
+    pub status: Option<u16>,
+    /// `CURLcode` if the transfer failed
+    pub error: Option<uint>,
+    pub duration_ms: u64,
+    /// Delay before the next attempt, 0 for the last one
+    pub delay_ms: u64,
+}
+
+impl RetryPolicy {
+    /// 3 attempts on network errors and 429, 502, 503, 504
+    pub fn new() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            errors: [CURLE_COULDNT_RESOLVE_HOST, CURLE_COULDNT_CONNECT, CURLE_OPERATION_TIMEDOUT,
+                     CURLE_SEND_ERROR, CURLE_RECV_ERROR, CURLE_GOT_NOTHING, CURLE_PARTIAL_FILE]
+                .iter().map(|&code| code as uint).collect(),
+            statuses: vec!(429, 502, 503, 504),
+            non_idempotent: false,
+            initial_delay_ms: 100,
+            max_delay_ms: 10000,
+            multiplier: 2.0,
+            jitter: 0.2,
+            respect_retry_after: true,
+            deadline_ms: None,
+        }
+    }
+
+    /// Whether repeating the method has no extra effect, see RFC 7231
+    pub fn is_idempotent(method: Method) -> bool {
+        match method {
+            Get | Head | Put | Delete => true,
+            Custom(name) => ["OPTIONS", "TRACE"].contains(&name),
+            _ => false
+        }
+    }
+
+    pub fn allows(&self, method: Method) -> bool {
+        self.non_idempotent || RetryPolicy::is_idempotent(method)
+    }
+
+    /// Delay after attempt `attempt` (starting from 1), `None` if
+    /// there's no time left for another one
+    pub fn delay(&self, attempt: uint, elapsed_ms: u64, headers: Option<&Headers>) -> Option<u64> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+
+        let retry_after = if self.respect_retry_after {
+            headers.and_then(retry_after_ms)
+        } else {
+            None
+        };
+        let delay = match retry_after {
+            Some(ms) => ms,
+            None => {
+                let base = self.initial_delay_ms as f64 * self.multiplier.powi(attempt as i32 - 1);
+                let factor = 1.0 + self.jitter * (2.0 * random::<f64>() - 1.0);
+                (base * factor) as u64
+            }
+        };
+        let delay = cmp::min(delay, self.max_delay_ms);
+
+        match self.deadline_ms {
+            Some(deadline) if elapsed_ms + delay >= deadline => None,
+            _ => Some(delay)
+        }
+    }
+}
+
+/// `Retry-After` in milliseconds, it is either seconds or a date
+pub fn retry_after_ms(headers: &Headers) -> Option<u64> {
+    let value = match headers.get("Retry-After") {
+        Some(v) => v,
+        None => return None
+    };
+    match from_str::<u64>(value) {
+        Some(secs) => Some(secs * 1000),
+        None => parse_http_date(value).map(|date| {
+            let ms = |t: time::Timespec| t.sec * 1000 + (t.nsec / 1000000) as i64;
+            let diff = ms(date.to_timespec()) - ms(time::get_time());
+            cmp::max(diff, 0) as u64
+        })
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::{RetryPolicy, retry_after_ms};
+    use http::{Client, Headers, Get, Post, Custom};
+    use http::headers::format_http_date;
+    use time;
+
+    #[test]
+    fn idempotency() {
+        let p = RetryPolicy::new();
+        assert!(p.allows(Get) && p.allows(Custom("OPTIONS")));
+        assert!(!p.allows(Post) && !p.allows(Custom("LOCK")));
+    }
+
+    #[test]
+    fn backoff() {
+        let mut p = RetryPolicy::new();
+        p.jitter = 0.0;
+        p.max_attempts = 5;
+        assert_eq!(p.delay(1, 0, None), Some(100));
+        assert_eq!(p.delay(3, 0, None), Some(400));
+        assert_eq!(p.delay(5, 0, None), None);
+
+        p.deadline_ms = Some(1000);
+        assert_eq!(p.delay(1, 950, None), None);
+
+        let mut h = Headers::new();
+        h.append("Retry-After", "2");
+        assert_eq!(p.delay(1, 0, Some(&h)), None);
+        p.deadline_ms = None;
+        assert_eq!(p.delay(1, 0, Some(&h)), Some(2000));
+
+        // Server can't make the client wait longer than it wants to
+        h.set("Retry-After", "3600");
+        assert_eq!(p.delay(1, 0, Some(&h)), Some(p.max_delay_ms));
+    }
+
+    #[test]
+    fn retry_after_date() {
+        let mut h = Headers::new();
+        let later = time::at_utc(time::Timespec::new(time::get_time().sec + 60, 0));
+        h.append("Retry-After", format_http_date(&later).as_slice());
+        let ms = retry_after_ms(&h).unwrap();
+        assert!(ms > 55000 && ms <= 60000);
+    }
+
+    #[test]
+    fn retried_status() {
+        let mut c = Client::new("http://httpbin.org/");
+        let mut p = RetryPolicy::new();
+        p.initial_delay_ms = 10;
+        c.set_retry_policy(p);
+
+        let mut req = c.new_get_request("status/503");
+        let resp = c.perform(&mut req).unwrap();
+        assert_eq!(resp.status_code, 503);
+        assert_eq!(resp.attempts.len(), 3);
+        assert!(resp.attempts.iter().all(|a| a.status == Some(503)));
+        assert_eq!(resp.attempts[2].delay_ms, 0);
+    }
+
+    #[test]
+    fn retried_error() {
+        let mut c = Client::new("http://127.0.0.1:1/");
+        let mut p = RetryPolicy::new();
+        p.initial_delay_ms = 10;
+        c.set_retry_policy(p);
+
+        let mut req = c.new_get_request("");
+        match c.perform(&mut req) {
+            Ok(_) => fail!("nothing listens on port 1"),
+            Err(e) => assert_eq!(e.attempts.len(), 3)
+        }
+    }
+}
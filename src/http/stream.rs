@@ -0,0 +1,289 @@
+use libc;
+use libc::{c_int, c_long, c_void, uintptr_t};
+use std::io::{IoResult, IoError, EndOfFile, OtherIoError};
+use std::{cmp, mem, ptr, slice};
+
+use easy;
+use easy::Curl;
+use info;
+use errors::{CURLE_OK, CURLE_FAILED_INIT};
+use multi::Multi;
+use opt;
+use super::{Client, CurlError, Request, Response, HeadParser, merge_cookies};
+use super::transfer::Setup;
+
+static CURL_WRITEFUNC_PAUSE: libc::size_t = 0x10000001;
+
+/// Transfer is paused once that much data is buffered
+/// and not consumed by the reader yet
+static BUFFER_LIMIT: uint = 256 * 1024;
+
+static WAIT_TIMEOUT_MS: uint = 1000;
+
+#[link(name = "curl")]
+extern {
+    fn curl_easy_getinfo(h: uintptr_t, inf: c_int, ptr: *mut c_void) -> c_int;
+}
+
+// Shared between the callbacks and the reader
+struct StreamState {
+    buf: Vec<u8>,
+    pos: uint,
+    paused: bool,
+    // Taken once the head of the final response is complete
+    parser: Option<HeadParser>,
+    head_done: bool,
+    // Raw handle for getinfo in the header callback
+    handle: uintptr_t,
+    // libcurl follows redirects, so their heads are not final
+    follows_redirects: bool,
+}
+
+impl StreamState {
+    // Whether the last complete head is the one of the final response,
+    // rather than an interim, proxy CONNECT or redirect response
+    fn is_final_head(&self, parser: &HeadParser) -> bool {
+        if !parser.is_complete() {
+            return false;
+        }
+        let head = match parser.last() {
+            Some(head) => head,
+            None => return false
+        };
+        let code = head.status.code;
+        if code < 200 {
+            return false;
+        }
+        if self.follows_redirects && code / 100 == 3 && head.headers.get("Location").is_some() {
+            return false;
+        }
+        // CONNECT status is not a response code of the transfer
+        let mut response_code: c_long = 0;
+        unsafe {
+            curl_easy_getinfo(self.handle, info::RESPONSE_CODE, &mut response_code as *mut c_long as *mut c_void);
+        }
+        response_code as u16 == code
+    }
+}
+
+/// Response body which is read while being downloaded
+///
+/// Transfer is driven by `read` through the multi interface,
+/// so nothing happens in between of calls. If the reader falls
+/// behind, the transfer is paused until buffered data is consumed.
+pub struct ResponseStream {
+    // Declared first, so it is dropped before the data it points to
+    transfer: Transfer,
+    finished: Option<uint>,
+}
+
+struct Transfer {
+    multi: Multi,
+    curl: Curl,
+    state: Box<StreamState>,
+    setup: Setup,
+}
+
+// Transfer runs on a copy of the client `session`
+pub fn start(session: &Curl, req: Request) -> Result<Response, CurlError> {
+    let multi = Multi::new();
+    if multi.is_null() {
+        return Err(CurlError::new(CURLE_FAILED_INIT as uint, "Can't create multi handle".to_string()));
+    }
+    let curl = session.duphandle();
+    let setup = try!(Setup::new(&curl, req));
+
+    let handle = curl.raw_handle();
+    let mut transfer = Transfer {
+        multi: multi,
+        curl: curl,
+        state: box StreamState {
+            buf: Vec::new(),
+            pos: 0,
+            paused: false,
+            parser: Some(HeadParser::new()),
+            head_done: false,
+            handle: handle,
+            follows_redirects: setup.request.redirects.max_redirects > 0,
+        },
+        setup: setup,
+    };
+
+    transfer.curl.set_data_func(opt::WRITEFUNCTION, stream_write_fn);
+    transfer.curl.set_data_func(opt::HEADERFUNCTION, stream_header_fn);
+    let state_ptr: *mut StreamState = &mut *transfer.state;
+    transfer.curl.setopt(opt::WRITEDATA, state_ptr);
+    transfer.curl.setopt(opt::HEADERDATA, state_ptr);
+
+    match transfer.multi.add(&transfer.curl) {
+        Ok(()) => (),
+        Err(e) => return Err(CurlError::new(CURLE_FAILED_INIT as uint, e.message))
+    }
+
+    let mut response = Response::new();
+    // Returns as soon as the head is there, body may take long or be absent
+    let mut finished = try!(transfer.step());
+    while !transfer.state.head_done && finished.is_none() {
+        transfer.wait();
+        finished = try!(transfer.step());
+    }
+
+    // Response is moved out, trailers are not tracked
+    response.set_heads(transfer.state.parser.take().unwrap());
+    // Set-Cookie of the final response and redirects is in
+    // the heads, so the client has all cookies from here on
+    merge_cookies(&transfer.curl, session);
+
+    match finished {
+        Some(code) if code != CURLE_OK as uint => return Err(transfer.error(code)),
+        _ => ()
+    }
+
+    Client::fill_response(&transfer.curl, &mut response);
+    if finished.is_none() {
+        // Still being received
+        response.compressed_size = None;
+    }
+    response.content_data = Some(box ResponseStream {
+        transfer: transfer,
+        finished: finished,
+    } as Box<Reader>);
+    Ok(response)
+}
+
+impl Transfer {
+    // Drives the transfer once without waiting,
+    // returns CURLcode when it is over
+    fn step(&mut self) -> Result<Option<uint>, CurlError> {
+        match self.multi.perform() {
+            Ok(_) => (),
+            Err(e) => return Err(CurlError::new(CURLE_FAILED_INIT as uint, e.message)),
+        }
+
+        match self.multi.info_read() {
+            Some(done) => Ok(Some(done.result)),
+            None => Ok(None)
+        }
+    }
+
+    // Waits for socket activity unless there is something to do already
+    fn wait(&mut self) {
+        // Paused transfer has nothing to wait for
+        if !self.state.paused && self.state.pos == self.state.buf.len() {
+            let _ = self.multi.wait(WAIT_TIMEOUT_MS);
+        }
+    }
+
+    fn error(&self, code: uint) -> CurlError {
+        self.setup.error(&self.curl, code)
+    }
+}
+
+impl Drop for Transfer {
+    fn drop(&mut self) {
+        // Aborts the transfer if it is still running
+        let _ = self.multi.remove(&self.curl);
+        self.setup.cleanup(&self.curl);
+    }
+}
+
+impl Reader for ResponseStream {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<uint> {
+        loop {
+            {
+                let state = &mut *self.transfer.state;
+                if state.pos < state.buf.len() {
+                    let n = cmp::min(buf.len(), state.buf.len() - state.pos);
+                    slice::bytes::copy_memory(buf, state.buf.slice(state.pos, state.pos + n));
+                    state.pos += n;
+                    if state.pos == state.buf.len() {
+                        state.buf.clear();
+                        state.pos = 0;
+                    }
+                    if state.paused && state.buf.len() - state.pos < BUFFER_LIMIT / 2 {
+                        state.paused = false;
+                        // May call the write callback right away
+                        self.transfer.curl.pause(easy::PAUSE_CONT);
+                    }
+                    return Ok(n);
+                }
+            }
+
+            match self.finished {
+                Some(code) if code == CURLE_OK as uint => return Err(IoError {
+                    kind: EndOfFile,
+                    desc: "end of response body",
+                    detail: None
+                }),
+                Some(code) => return Err(IoError {
+                    kind: OtherIoError,
+                    desc: "transfer failed",
+                    detail: Some(self.transfer.error(code).message)
+                }),
+                None => ()
+            }
+
+            if self.finished.is_none() {
+                self.transfer.wait();
+            }
+            self.finished = match self.transfer.step() {
+                Ok(finished) => finished,
+                Err(e) => return Err(IoError {
+                    kind: OtherIoError,
+                    desc: "transfer failed",
+                    detail: Some(e.message)
+                }),
+            };
+        }
+    }
+}
+
+// Write expects user_data to be *StreamState
+fn stream_write_fn(p: *mut u8, size: libc::size_t, nmemb: libc::size_t,
+                   user_data: *mut libc::c_void) -> libc::size_t {
+    let state: *mut StreamState = unsafe { mem::transmute(user_data) };
+    if state == ptr::mut_null() {
+        return size * nmemb;
+    }
+
+    unsafe {
+        if (*state).buf.len() - (*state).pos >= BUFFER_LIMIT {
+            // Same data is passed again after unpausing
+            (*state).paused = true;
+            return CURL_WRITEFUNC_PAUSE;
+        }
+        slice::raw::buf_as_slice(p as *const u8, (size * nmemb) as uint, |data| {
+            (*state).buf.push_all(data);
+        });
+    }
+    size * nmemb
+}
+
+// Header expects user_data to be *StreamState
+fn stream_header_fn(p: *mut u8, size: libc::size_t, nmemb: libc::size_t,
+                    user_data: *mut libc::c_void) -> libc::size_t {
+    let state: *mut StreamState = unsafe { mem::transmute(user_data) };
+    if state == ptr::mut_null() {
+        return size * nmemb;
+    }
+
+    unsafe {
+        // Trailers arrive after the parser is moved to the response
+        match (*state).parser {
+            Some(ref mut parser) => {
+                slice::raw::buf_as_slice(p as *const u8, (size * nmemb) as uint, |line| {
+                    parser.feed(line);
+                });
+            },
+            None => return size * nmemb
+        }
+        let done = match (*state).parser {
+            Some(ref parser) => (*state).is_final_head(parser),
+            None => false
+        };
+        if done {
+            (*state).head_done = true;
+        }
+    }
+    size * nmemb
+}
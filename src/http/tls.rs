@@ -0,0 +1,335 @@
+use libc::{c_char, c_int, c_long, c_void, uintptr_t};
+use std::c_str::CString;
+use std::ptr;
+
+use easy::Curl;
+use opt;
+
+#[link(name = "curl")]
+extern {
+    fn curl_easy_getinfo(h: uintptr_t, inf: c_int, ptr: *mut c_void) -> c_int;
+}
+
+// CURLINFO_CAINFO and CURLINFO_CAPATH, built-in CA locations
+static INFO_CAINFO: c_int = 0x100000 + 61;
+static INFO_CAPATH: c_int = 0x100000 + 62;
+
+// CURL_SSLVERSION_* values, maximum goes to the upper 16 bits
+static SSLVERSION_MAX_SHIFT: uint = 16;
+
+// CURLSSLOPT_* bits
+static SSLOPT_NO_REVOKE: c_long = 1 << 1;
+static SSLOPT_REVOKE_BEST_EFFORT: c_long = 1 << 3;
+static SSLOPT_NATIVE_CA: c_long = 1 << 4;
+
+#[deriving(Clone, Show, PartialEq, PartialOrd)]
+pub enum TlsVersion {
+    Tls10,
+    Tls11,
+    Tls12,
+    Tls13,
+}
+
+/// Format of certificate and key files
+#[deriving(Clone, Show, PartialEq)]
+pub enum CertFormat {
+    Pem,
+    Der,
+    /// PKCS#12, holds both certificate and key
+    P12,
+}
+
+/// How certificate revocation is checked
+#[deriving(Clone, Show, PartialEq)]
+pub enum Revocation {
+    /// Whatever the TLS backend does by default
+    DefaultRevocation,
+    /// Don't check, Schannel only
+    NoRevocation,
+    /// Ignore missing or offline distribution points, Schannel only
+    BestEffortRevocation,
+    /// Require stapled OCSP response from the server
+    OcspStapling,
+}
+
+/// TLS settings of a `Client` or a single `Request`
+///
+/// Options which are not set keep libcurl defaults, i.e. the
+/// peer is verified against the built-in CA bundle.
+#[deriving(Clone, Show, PartialEq)]
+pub struct TlsConfig {
+    verify_peer: bool,
+    verify_host: bool,
+    ca_file: Option<Path>,
+    ca_path: Option<Path>,
+    native_ca: bool,
+    cert: Option<(Path, CertFormat)>,
+    key: Option<(Path, CertFormat)>,
+    password: Option<String>,
+    min_version: Option<TlsVersion>,
+    max_version: Option<TlsVersion>,
+    ciphers: Option<String>,
+    crl_file: Option<Path>,
+    issuer_cert: Option<Path>,
+    pinned_keys: Vec<String>,
+    revocation: Revocation,
+}
+
+fn version_value(v: TlsVersion) -> c_long {
+    match v {
+        Tls10 => 4,
+        Tls11 => 5,
+        Tls12 => 6,
+        Tls13 => 7,
+    }
+}
+
+fn format_name(f: CertFormat) -> &'static str {
+    match f {
+        Pem => "PEM",
+        Der => "DER",
+        P12 => "P12",
+    }
+}
+
+impl TlsConfig {
+    pub fn new() -> TlsConfig {
+        TlsConfig {
+            verify_peer: true,
+            verify_host: true,
+            ca_file: None,
+            ca_path: None,
+            native_ca: false,
+            cert: None,
+            key: None,
+            password: None,
+            min_version: None,
+            max_version: None,
+            ciphers: None,
+            crl_file: None,
+            issuer_cert: None,
+            pinned_keys: Vec::new(),
+            revocation: DefaultRevocation,
+        }
+    }
+
+    /// Turns off verification of the certificate chain, don't
+    /// do that unless the peer is pinned by `pin_public_key`
+    pub fn verify_peer(&mut self, verify: bool) -> &mut TlsConfig {
+        self.verify_peer = verify;
+        self
+    }
+
+    /// Turns off check that the certificate matches the host name
+    pub fn verify_host(&mut self, verify: bool) -> &mut TlsConfig {
+        self.verify_host = verify;
+        self
+    }
+
+    /// CA bundle file instead of the built-in one
+    pub fn ca_file(&mut self, path: &Path) -> &mut TlsConfig {
+        self.ca_file = Some(path.clone());
+        self
+    }
+
+    /// Directory with hashed CA certificates (OpenSSL `c_rehash` layout)
+    pub fn ca_path(&mut self, path: &Path) -> &mut TlsConfig {
+        self.ca_path = Some(path.clone());
+        self
+    }
+
+    /// Use CA store of the operating system
+    pub fn native_ca(&mut self, native: bool) -> &mut TlsConfig {
+        self.native_ca = native;
+        self
+    }
+
+    /// Client certificate, P12 one holds the key as well
+    pub fn client_cert(&mut self, path: &Path, format: CertFormat) -> &mut TlsConfig {
+        self.cert = Some((path.clone(), format));
+        self
+    }
+
+    /// Private key of the client certificate
+    pub fn client_key(&mut self, path: &Path, format: CertFormat) -> &mut TlsConfig {
+        self.key = Some((path.clone(), format));
+        self
+    }
+
+    /// Password of the private key or P12 file
+    pub fn key_password(&mut self, password: &str) -> &mut TlsConfig {
+        self.password = Some(password.to_string());
+        self
+    }
+
+    pub fn min_version(&mut self, version: TlsVersion) -> &mut TlsConfig {
+        self.min_version = Some(version);
+        self
+    }
+
+    pub fn max_version(&mut self, version: TlsVersion) -> &mut TlsConfig {
+        self.max_version = Some(version);
+        self
+    }
+
+    /// Cipher list in the TLS backend syntax, i.e. OpenSSL one
+    /// like `ECDHE+AESGCM:!aNULL`
+    pub fn ciphers(&mut self, ciphers: &str) -> &mut TlsConfig {
+        self.ciphers = Some(ciphers.to_string());
+        self
+    }
+
+    /// Certificate revocation list in PEM format
+    pub fn crl_file(&mut self, path: &Path) -> &mut TlsConfig {
+        self.crl_file = Some(path.clone());
+        self
+    }
+
+    /// Peer certificate must be issued by this one
+    pub fn issuer_cert(&mut self, path: &Path) -> &mut TlsConfig {
+        self.issuer_cert = Some(path.clone());
+        self
+    }
+
+    /// Adds an allowed public key, either `sha256//<base64 hash>`
+    /// or a path to PEM/DER key file. Connection fails unless
+    /// the server key is one of the pinned ones.
+    pub fn pin_public_key(&mut self, key: &str) -> &mut TlsConfig {
+        self.pinned_keys.push(key.to_string());
+        self
+    }
+
+    pub fn revocation(&mut self, revocation: Revocation) -> &mut TlsConfig {
+        self.revocation = revocation;
+        self
+    }
+
+    /// Sets every TLS option of the handle, the ones which are
+    /// not configured go back to libcurl defaults
+    pub fn apply(&self, session: &Curl) {
+        session.setopt(opt::SSL_VERIFYPEER, self.verify_peer);
+        session.setopt(opt::SSL_VERIFYHOST, if self.verify_host { 2u } else { 0u });
+
+        // Null disables the CA bundle, so the built-in one is set back
+        let set_ca = |option, info, path: &Option<Path>| match *path {
+            Some(ref p) => { session.setopt(option, p.as_vec()); },
+            None => match default_ca(session, info) {
+                Some(ref default) => { session.setopt(option, default.as_bytes_no_nul()); },
+                None => { session.setopt(option, 0u); }
+            }
+        };
+        set_ca(opt::CAINFO, INFO_CAINFO, &self.ca_file);
+        set_ca(opt::CAPATH, INFO_CAPATH, &self.ca_path);
+
+        let set_path = |option, path: &Option<Path>| match *path {
+            Some(ref p) => { session.setopt(option, p.as_vec()); },
+            None => { session.setopt(option, 0u); }
+        };
+        set_path(opt::CRLFILE, &self.crl_file);
+        set_path(opt::ISSUERCERT, &self.issuer_cert);
+
+        let (cert, cert_format) = match self.cert {
+            Some((ref path, format)) => (Some(path.clone()), format),
+            None => (None, Pem)
+        };
+        set_path(opt::SSLCERT, &cert);
+        session.setopt(opt::SSLCERTTYPE, format_name(cert_format));
+        let (key, key_format) = match self.key {
+            Some((ref path, format)) => (Some(path.clone()), format),
+            None => (None, Pem)
+        };
+        set_path(opt::SSLKEY, &key);
+        session.setopt(opt::SSLKEYTYPE, format_name(key_format));
+        match self.password {
+            Some(ref password) => { session.setopt(opt::KEYPASSWD, password.as_slice()); },
+            None => { session.setopt(opt::KEYPASSWD, 0u); }
+        }
+
+        let min = self.min_version.map(version_value).unwrap_or(0);
+        let max = self.max_version.map(version_value).unwrap_or(0);
+        session.setopt(opt::SSLVERSION, (min | (max << SSLVERSION_MAX_SHIFT)) as int);
+
+        match self.ciphers {
+            Some(ref ciphers) => { session.setopt(opt::SSL_CIPHER_LIST, ciphers.as_slice()); },
+            None => { session.setopt(opt::SSL_CIPHER_LIST, 0u); }
+        }
+        if self.pinned_keys.is_empty() {
+            session.setopt(opt::PINNEDPUBLICKEY, 0u);
+        } else {
+            session.setopt(opt::PINNEDPUBLICKEY, self.pinned_keys.connect(";").as_slice());
+        }
+
+        let mut options = if self.native_ca { SSLOPT_NATIVE_CA } else { 0 };
+        match self.revocation {
+            NoRevocation => options |= SSLOPT_NO_REVOKE,
+            BestEffortRevocation => options |= SSLOPT_REVOKE_BEST_EFFORT,
+            _ => ()
+        }
+        session.setopt(opt::SSL_OPTIONS, options as int);
+        session.setopt(opt::SSL_VERIFYSTATUS, self.revocation == OcspStapling);
+    }
+}
+
+// Built-in CA location, None if libcurl has none or is too old to tell
+fn default_ca(session: &Curl, info: c_int) -> Option<CString> {
+    let mut value: *const c_char = ptr::null();
+    let res = unsafe {
+        curl_easy_getinfo(session.raw_handle(), info, &mut value as *mut *const c_char as *mut c_void)
+    };
+    if res != 0 || value.is_null() {
+        return None;
+    }
+    // Copied, the string belongs to libcurl
+    Some(unsafe { CString::new(value, false) }.as_bytes_no_nul().to_c_str())
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::{TlsConfig, Tls12};
+    use http::Client;
+
+    #[test]
+    fn wrong_pin() {
+        let mut c = Client::new("https://httpbin.org/");
+        let mut tls = TlsConfig::new();
+        tls.min_version(Tls12);
+        c.set_tls(tls.clone());
+        let mut req = c.new_get_request("get");
+        assert_eq!(c.perform(&mut req).unwrap().status_code, 200);
+
+        tls.pin_public_key("sha256//AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=");
+        req.tls = Some(tls);
+        assert!(c.perform(&mut req).is_err());
+
+        // Client settings are intact
+        req.tls = None;
+        assert_eq!(c.perform(&mut req).unwrap().status_code, 200);
+    }
+
+    #[test]
+    fn request_settings_replace_client_ones() {
+        let mut c = Client::new("https://httpbin.org/");
+        let mut tls = TlsConfig::new();
+        tls.pin_public_key("sha256//AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=");
+        c.set_tls(tls);
+
+        // Pin of the client is not inherited
+        let mut req = c.new_get_request("cookies/set?tls=1");
+        req.tls = Some(TlsConfig::new());
+        assert_eq!(c.perform(&mut req).unwrap().status_code, 302);
+
+        // Cookie received by the request is the client one
+        let cookies = c.cookie_jar().list();
+        assert!(cookies.iter().any(|cookie| cookie.name.as_slice() == "tls"));
+        let mut req = c.new_get_request("cookies");
+        req.tls = Some(TlsConfig::new());
+        let content = c.perform(&mut req).unwrap().content_data.unwrap().read_to_string().unwrap();
+        assert!(content.as_slice().contains("\"tls\": \"1\""));
+
+        // Client settings are replaced as a whole
+        c.set_tls(TlsConfig::new());
+        let mut req = c.new_get_request("get");
+        assert_eq!(c.perform(&mut req).unwrap().status_code, 200);
+    }
+}
@@ -0,0 +1,213 @@
+use libc;
+use std::io::IoError;
+use std::{mem, ptr, slice};
+
+use easy;
+use easy::Curl;
+use errors::{CURLE_OK, CURLE_BAD_FUNCTION_ARGUMENT, CURLE_WRITE_ERROR};
+use handlers::{Handler, MemoryHandler};
+use mime::Mime;
+use opt;
+use super::{encoding, Client, CurlError, Request, Response, BodySource, Headers, HeadParser,
+            ProxyStatus, Hop, CURL_ERROR_SIZE};
+
+// Shared between the write callback and the transfer
+struct Sink {
+    handler: Option<Box<Handler+Send>>,
+    parser: HeadParser,
+    head_done: bool,
+    write_error: Option<IoError>,
+}
+
+/// Data a handle set up for a request points to, except
+/// the sink of the response, see `Transfer` and streaming
+///
+/// The handle should be dropped first, or data pointers
+/// are cleared by `cleanup`.
+pub struct Setup {
+    // Source borrows from request, so it goes first
+    source: Option<Box<BodySource<'static>>>,
+    mime: Option<Mime>,
+    pub request: Box<Request>,
+    error_buf: Vec<u8>,
+}
+
+impl Setup {
+    /// Sets `curl` up for the request, libcurl follows redirects
+    pub fn new(curl: &Curl, req: Request) -> Result<Setup, CurlError> {
+        // Client writes the jar file, not the copy, even
+        // if the copy is dropped because of an error here
+        curl.setopt(opt::COOKIEJAR, 0u);
+
+        // libcurl has no per hop checks
+        if !req.redirects.is_delegable() {
+            return Err(CurlError::new(CURLE_BAD_FUNCTION_ARGUMENT as uint,
+                                      "Same host and filtered redirects need Client::perform".to_string()));
+        }
+        let request = box req;
+        match request.tls {
+            Some(ref tls) => tls.apply(curl),
+            None => ()
+        }
+        let (source, mime) = {
+            // Request is boxed and owned by the setup, so the
+            // borrow is valid as long as the setup is alive
+            let req_ref: &'static Request = unsafe { mem::transmute(&*request) };
+            try!(Client::prepare(curl, req_ref, &Hop::first(req_ref)))
+        };
+        request.redirects.apply(curl);
+
+        let mut setup = Setup {
+            source: source.map(|s| box s),
+            mime: mime,
+            request: request,
+            error_buf: Vec::from_elem(CURL_ERROR_SIZE + 1, 0u8),
+        };
+
+        // Duplicated handle still points to the client buffers
+        curl.setopt(opt::ERRORBUFFER, setup.error_buf.as_mut_ptr());
+        match setup.source {
+            Some(ref mut s) => Client::set_source(curl, &mut **s as *mut BodySource),
+            None => Client::set_source(curl, ptr::mut_null()),
+        }
+        Ok(setup)
+    }
+
+    /// Clears data pointers of `curl`, the transfer must be over
+    pub fn cleanup(&mut self, curl: &Curl) {
+        Client::cleanup(curl, self.mime.take());
+    }
+
+    /// Error of the failed transfer with CURLcode `code`
+    pub fn error(&self, curl: &Curl, code: uint) -> CurlError {
+        let message = self.error_buf.as_slice().to_c_str().as_str().unwrap_or("").to_string();
+        let mut err = CurlError::new(code, if message.len() > 0 { message } else { easy::strerror(code as int) });
+        err.proxy = ProxyStatus::from_session(curl);
+        err
+    }
+}
+
+/// Request performed by a handle of the multi interface,
+/// keeps the data the handle points to
+///
+/// The handle should be dropped first, or data pointers
+/// are cleared by `complete`.
+pub struct Transfer {
+    sink: Box<Sink>,
+    setup: Setup,
+}
+
+impl Transfer {
+    /// Sets `curl` up for the request, libcurl follows redirects
+    pub fn new(curl: &Curl, req: Request) -> Result<Transfer, CurlError> {
+        let setup = try!(Setup::new(curl, req));
+        let mut handler = match setup.request.handler.borrow_mut().take() {
+            Some(h) => h,
+            None => box MemoryHandler::new() as Box<Handler+Send>,
+        };
+        if setup.request.accept_encoding.decodes_in_rust() {
+            handler = box encoding::Decoder::new(handler) as Box<Handler+Send>;
+        }
+        // Waits for a connection which may be multiplexed
+        // rather than opening another one
+        curl.setopt(opt::PIPEWAIT, true);
+
+        let mut transfer = Transfer {
+            sink: box Sink {
+                handler: Some(handler),
+                parser: HeadParser::new(),
+                head_done: false,
+                write_error: None,
+            },
+            setup: setup,
+        };
+
+        curl.set_data_func(opt::WRITEFUNCTION, transfer_write_fn);
+        let sink_ptr: *mut Sink = &mut *transfer.sink;
+        curl.setopt(opt::WRITEDATA, sink_ptr);
+        curl.setopt(opt::HEADERDATA, &mut transfer.sink.parser as *mut HeadParser);
+        Ok(transfer)
+    }
+
+    /// Finishes the response once the multi handle reports
+    /// the transfer is over with CURLcode `code`
+    pub fn complete(mut self, curl: &Curl, code: uint) -> Result<Response, CurlError> {
+        self.setup.cleanup(curl);
+        let mut response = Response::new();
+        response.set_heads(mem::replace(&mut self.sink.parser, HeadParser::new()));
+        let mut handler = self.sink.handler.take().unwrap();
+
+        if code != CURLE_OK as uint {
+            handler.abort();
+            return Err(match self.sink.write_error.take() {
+                Some(e) => {
+                    let mut err = CurlError::new(code, e.to_string());
+                    err.proxy = ProxyStatus::from_session(curl);
+                    err
+                },
+                None => self.setup.error(curl, code)
+            });
+        }
+
+        Client::fill_response(curl, &mut response);
+        // Body-less responses still have a head
+        let head = if self.sink.head_done {
+            Ok(())
+        } else {
+            handler.head(response.status_code, &response.headers)
+        };
+        match head.and_then(|_| handler.finish()) {
+            Ok(()) => {
+                response.content_data = handler.into_reader();
+                Ok(response)
+            },
+            Err(e) => {
+                handler.abort();
+                Err(CurlError::new(CURLE_WRITE_ERROR as uint, e.to_string()))
+            }
+        }
+    }
+}
+
+impl Sink {
+    // Returns the count of consumed bytes, less is an error
+    fn write(&mut self, data: &[u8]) -> uint {
+        let handler = self.handler.as_mut().unwrap();
+        if !self.head_done {
+            self.head_done = true;
+            let res = match self.parser.last() {
+                Some(head) => handler.head(head.status.code, &head.headers),
+                None => handler.head(0, &Headers::new()),
+            };
+            match res {
+                Ok(()) => (),
+                Err(e) => {
+                    self.write_error = Some(e);
+                    return 0;
+                }
+            }
+        }
+        match handler.write(data) {
+            Ok(()) => data.len(),
+            Err(e) => {
+                self.write_error = Some(e);
+                0
+            }
+        }
+    }
+}
+
+// Write expects user_data to be *Sink
+fn transfer_write_fn(p: *mut u8, size: libc::size_t, nmemb: libc::size_t,
+                     user_data: *mut libc::c_void) -> libc::size_t {
+    let sink: *mut Sink = unsafe { mem::transmute(user_data) };
+    if sink == ptr::mut_null() {
+        return size * nmemb;
+    }
+
+    unsafe {
+        slice::raw::buf_as_slice(p as *const u8, (size * nmemb) as uint, |data| {
+            (*sink).write(data) as libc::size_t
+        })
+    }
+}
@@ -4,6 +4,7 @@ static CURLINFO_STRING : c_int = 0x100000;
 static CURLINFO_LONG   : c_int = 0x200000;
 static CURLINFO_DOUBLE : c_int = 0x300000;
 static CURLINFO_SLIST  : c_int = 0x400000;
+static CURLINFO_SOCKET : c_int = 0x500000;
 
 // #define CURLINFO_MASK     0x0fffff
 // #define CURLINFO_TYPEMASK 0xf00000
@@ -50,6 +51,8 @@ pub static RTSP_CSEQ_RECV   : c_int = CURLINFO_LONG   + 39;
 pub static PRIMARY_PORT     : c_int = CURLINFO_LONG   + 40;
 pub static LOCAL_IP         : c_int = CURLINFO_STRING + 41;
 pub static LOCAL_PORT       : c_int = CURLINFO_LONG   + 42;
+pub static ACTIVESOCKET     : c_int = CURLINFO_SOCKET + 44;
+pub static HTTP_VERSION     : c_int = CURLINFO_LONG   + 46;
   /* Fill in new entries below here! */
 
 /* CURLINFO_RESPONSE_CODE is the new name for the option previously known as
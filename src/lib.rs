@@ -3,23 +3,35 @@
 #![crate_type = "dylib"]
 #![desc = "A rust package for libcurl."]
 #![license = "MIT"]
-#![feature(phase)]
+#![feature(phase, unboxed_closures, overloaded_calls, unsafe_destructor)]
 
+extern crate flate;
 extern crate libc;
-extern crate regex;
+extern crate serialize;
+extern crate time;
 
 #[phase(plugin, link)] extern crate log;
-#[phase(plugin)] extern crate regex_macros;
 
-use libc::{c_char, c_long, c_int};
+use libc::{c_char, c_long, c_int, c_uint};
 use std::c_str::CString;
 use std::path::BytesContainer;
 
 pub use self::easy::Curl as Curl;
 
+// Leading fields of curl_version_info_data, the rest is not used
+#[repr(C)]
+struct VersionInfo {
+    age: c_int,
+    version: *const c_char,
+    version_num: c_uint,
+    host: *const c_char,
+    features: c_int,
+}
+
 #[link(name = "curl")]
 extern {
     fn curl_version() -> *const c_char;
+    fn curl_version_info(age: c_int) -> *const VersionInfo;
     fn curl_global_init(flags: c_long) -> c_int;
     fn curl_global_cleanup();
 }
@@ -31,6 +43,12 @@ pub static GLOBAL_NOTHING : c_long = 0;
 pub static GLOBAL_DEFAULT : c_long = GLOBAL_ALL;
 pub static GLOBAL_ACK_EINTR : c_long = (1<<2);
 
+static CURLVERSION_FOURTH : c_int = 3;
+
+pub static VERSION_LIBZ : c_int = (1<<3);
+pub static VERSION_BROTLI : c_int = (1<<23);
+pub static VERSION_ZSTD : c_int = (1<<26);
+
 pub fn global_init(flags: c_long) -> int {
     unsafe { curl_global_init(flags) as int }
 }
@@ -47,12 +65,24 @@ pub fn version() -> String {
     }
 }
 
+/// Features of the linked libcurl, `VERSION_*` bits
+pub fn features() -> c_int {
+    unsafe { (*curl_version_info(CURLVERSION_FOURTH)).features }
+}
+
 pub mod handlers;
 pub mod http;
 pub mod easy;
+#[cfg(target_os = "linux")]
+pub mod epoll;
 pub mod errors;
 pub mod info;
+pub mod mime;
+pub mod multi;
 pub mod opt;
+pub mod share;
+pub mod url;
+pub mod websocket;
 
 #[cfg(test)]
 mod test;
@@ -0,0 +1,367 @@
+use libc::{uintptr_t, c_int, c_char, size_t, c_void};
+use std::cell::RefCell;
+use std::io::{EndOfFile, IoError};
+use std::{mem, ptr, slice};
+
+use easy::Curl;
+use opt;
+
+#[allow(dead_code)]
+#[link(name = "curl")]
+extern {
+    fn curl_mime_init(easy: uintptr_t) -> uintptr_t;
+    fn curl_mime_free(mime: uintptr_t);
+    fn curl_mime_addpart(mime: uintptr_t) -> uintptr_t;
+    fn curl_mime_name(part: uintptr_t, name: *const c_char) -> c_int;
+    fn curl_mime_filename(part: uintptr_t, filename: *const c_char) -> c_int;
+    fn curl_mime_type(part: uintptr_t, mimetype: *const c_char) -> c_int;
+    fn curl_mime_encoder(part: uintptr_t, encoding: *const c_char) -> c_int;
+    fn curl_mime_data(part: uintptr_t, data: *const c_char, datasize: size_t) -> c_int;
+    fn curl_mime_filedata(part: uintptr_t, filename: *const c_char) -> c_int;
+    fn curl_mime_data_cb(part: uintptr_t, datasize: i64,
+                         readfunc: extern "C" fn(*mut c_char, size_t, size_t, *mut c_void) -> size_t,
+                         seekfunc: uintptr_t, freefunc: uintptr_t, arg: *mut c_void) -> c_int;
+    fn curl_mime_subparts(part: uintptr_t, subparts: uintptr_t) -> c_int;
+    fn curl_mime_headers(part: uintptr_t, headers: uintptr_t, take_ownership: c_int) -> c_int;
+    fn curl_slist_append(list: uintptr_t, string: *const c_char) -> uintptr_t;
+}
+
+static CURL_READFUNC_ABORT: size_t = 0x10000000;
+
+/// Where the content of a single part comes from
+pub enum PartData {
+    /// In-memory data, copied by libcurl
+    InMemory(Vec<u8>),
+    /// File which is read by libcurl at transfer time
+    FromFile(Path),
+    /// Arbitrary reader with an optional known length.
+    ///
+    /// Readers can be consumed only once, so a form with
+    /// such a part can be attached to a single transfer only.
+    FromReader(RefCell<Option<Box<Reader+Send>>>, Option<u64>),
+    /// Nested multipart, i.e. multipart/mixed inside of form-data
+    Nested(Form),
+}
+
+/// Single part of a multipart body
+pub struct Part {
+    name: Option<String>,
+    filename: Option<String>,
+    content_type: Option<String>,
+    encoder: Option<String>,
+    headers: Vec<String>,
+    data: PartData,
+}
+
+/// Multipart body builder
+///
+/// It is only a description of the body, actual `curl_mime`
+/// structure is built by `Mime::new` right before the transfer,
+/// so the same form can be sent by different handles.
+///
+/// ```ignore
+/// let mut form = Form::new();
+/// form.add_text("name", "value");
+/// form.add_file("upload", &Path::new("report.csv"))
+///     .content_type("text/csv")
+///     .filename("today.csv");
+/// ```
+pub struct Form {
+    parts: Vec<Part>,
+}
+
+/// `curl_mime` handle built from a `Form`
+///
+/// Must outlive the transfer it is attached to.
+pub struct Mime {
+    handle: uintptr_t,
+    // Boxed so addresses passed to curl stay stable
+    streams: Vec<Box<StreamSource>>,
+}
+
+struct StreamSource {
+    reader: Box<Reader+Send>,
+}
+
+impl Part {
+    fn new(data: PartData) -> Part {
+        Part {
+            name: None,
+            filename: None,
+            content_type: None,
+            encoder: None,
+            headers: Vec::new(),
+            data: data,
+        }
+    }
+
+    /// Sets form field name
+    pub fn name<'a>(&'a mut self, name: &str) -> &'a mut Part {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    /// Sets remote file name, for file parts it defaults to
+    /// the local file name
+    pub fn filename<'a>(&'a mut self, filename: &str) -> &'a mut Part {
+        self.filename = Some(filename.to_string());
+        self
+    }
+
+    /// Sets part Content-Type
+    pub fn content_type<'a>(&'a mut self, content_type: &str) -> &'a mut Part {
+        self.content_type = Some(content_type.to_string());
+        self
+    }
+
+    /// Sets Content-Transfer-Encoding (`binary`, `8bit`, `7bit`,
+    /// `base64` or `quoted-printable`), mostly useful for mail
+    pub fn encoder<'a>(&'a mut self, encoder: &str) -> &'a mut Part {
+        self.encoder = Some(encoder.to_string());
+        self
+    }
+
+    /// Adds a custom header to this part
+    pub fn header<'a>(&'a mut self, name: &str, value: &str) -> &'a mut Part {
+        self.headers.push(format!("{}: {}", name, value));
+        self
+    }
+}
+
+impl Form {
+    pub fn new() -> Form {
+        Form {
+            parts: Vec::new()
+        }
+    }
+
+    /// Whether the form can be sent more than once,
+    /// reader parts are consumed by the first transfer
+    pub fn is_replayable(&self) -> bool {
+        self.parts.iter().all(|part| match part.data {
+            FromReader(..) => false,
+            Nested(ref form) => form.is_replayable(),
+            _ => true
+        })
+    }
+
+    pub fn len(&self) -> uint {
+        self.parts.len()
+    }
+
+    /// Adds a part without a name, useful for nested multiparts and mail
+    pub fn add_part<'a>(&'a mut self, data: PartData) -> &'a mut Part {
+        self.parts.push(Part::new(data));
+        self.parts.last_mut().unwrap()
+    }
+
+    /// Adds a text field
+    pub fn add_text<'a>(&'a mut self, name: &str, value: &str) -> &'a mut Part {
+        self.add_part(InMemory(value.as_bytes().to_vec())).name(name)
+    }
+
+    /// Adds a field with in-memory data, sent as a file
+    /// if `filename` is set on the returned part
+    pub fn add_data<'a>(&'a mut self, name: &str, data: &[u8]) -> &'a mut Part {
+        self.add_part(InMemory(data.to_vec())).name(name)
+    }
+
+    /// Adds a file field, the file is read during the transfer
+    pub fn add_file<'a>(&'a mut self, name: &str, path: &Path) -> &'a mut Part {
+        self.add_part(FromFile(path.clone())).name(name)
+    }
+
+    /// Adds a field which content is pulled from a reader.
+    /// If `len` is unknown, chunked encoding is used.
+    pub fn add_reader<'a>(&'a mut self, name: &str, reader: Box<Reader+Send>,
+                          len: Option<u64>) -> &'a mut Part {
+        self.add_part(FromReader(RefCell::new(Some(reader)), len)).name(name)
+    }
+
+    /// Adds a nested multipart
+    pub fn add_multipart<'a>(&'a mut self, name: &str, form: Form) -> &'a mut Part {
+        self.add_part(Nested(form)).name(name)
+    }
+}
+
+impl Mime {
+    /// Builds `curl_mime` for the form using `curl` handle.
+    ///
+    /// Reader parts are taken out of the form, an error
+    /// is returned if one was already consumed.
+    pub fn new(curl: &Curl, form: &Form) -> Result<Mime, String> {
+        let mut mime = Mime {
+            handle: unsafe { curl_mime_init(curl.raw_handle()) },
+            streams: Vec::new(),
+        };
+
+        if mime.handle == 0 {
+            return Err("curl_mime_init failed".to_string());
+        }
+
+        let handle = mime.handle;
+        match mime.fill(handle, curl, form) {
+            Ok(()) => Ok(mime),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn raw(&self) -> uintptr_t {
+        self.handle
+    }
+
+    /// Attaches this body to the handle through `opt::MIMEPOST`.
+    ///
+    /// Works for any protocol which sends mime data,
+    /// e.g. SMTP messages as well as HTTP posts.
+    pub fn attach(&self, curl: &Curl) -> int {
+        curl.setopt(opt::MIMEPOST, self.handle)
+    }
+
+    fn fill(&mut self, mime: uintptr_t, curl: &Curl, form: &Form) -> Result<(), String> {
+        for part in form.parts.iter() {
+            let handle = unsafe { curl_mime_addpart(mime) };
+            if handle == 0 {
+                return Err("curl_mime_addpart failed".to_string());
+            }
+            try!(self.fill_part(handle, curl, part));
+        }
+        Ok(())
+    }
+
+    fn fill_part(&mut self, handle: uintptr_t, curl: &Curl, part: &Part) -> Result<(), String> {
+        let res = match part.data {
+            InMemory(ref bytes) => unsafe {
+                curl_mime_data(handle, bytes.as_ptr() as *const c_char, bytes.len() as size_t)
+            },
+            FromFile(ref path) => path.with_c_str(|p| unsafe { curl_mime_filedata(handle, p) }),
+            FromReader(ref cell, len) => {
+                let reader = match cell.borrow_mut().take() {
+                    Some(r) => r,
+                    None => return Err("reader part was already consumed".to_string()),
+                };
+                let mut source = box StreamSource { reader: reader };
+                let arg: *mut StreamSource = &mut *source;
+                self.streams.push(source);
+                let size = match len {
+                    Some(l) => l as i64,
+                    None => -1,
+                };
+                unsafe { curl_mime_data_cb(handle, size, mime_read_fn, 0, 0, arg as *mut c_void) }
+            },
+            Nested(ref form) => {
+                let sub = unsafe { curl_mime_init(curl.raw_handle()) };
+                if sub == 0 {
+                    return Err("curl_mime_init failed".to_string());
+                }
+                let res = unsafe { curl_mime_subparts(handle, sub) };
+                if res != 0 {
+                    // Not owned by the part then
+                    unsafe { curl_mime_free(sub) };
+                    return check(res, "subparts");
+                }
+                // Freed together with the part from now on
+                try!(self.fill(sub, curl, form));
+                res
+            },
+        };
+        try!(check(res, "data"));
+
+        match part.name {
+            Some(ref name) => try!(check(name.with_c_str(|s| unsafe { curl_mime_name(handle, s) }), "name")),
+            None => ()
+        }
+        match part.filename {
+            Some(ref name) => try!(check(name.with_c_str(|s| unsafe { curl_mime_filename(handle, s) }), "filename")),
+            None => ()
+        }
+        match part.content_type {
+            Some(ref ct) => try!(check(ct.with_c_str(|s| unsafe { curl_mime_type(handle, s) }), "type")),
+            None => ()
+        }
+        match part.encoder {
+            Some(ref enc) => try!(check(enc.with_c_str(|s| unsafe { curl_mime_encoder(handle, s) }), "encoder")),
+            None => ()
+        }
+        if part.headers.len() > 0 {
+            let list = part.headers.iter().fold(0, |acc, item| {
+                item.with_c_str(|s| unsafe { curl_slist_append(acc, s) })
+            });
+            // take_ownership = 1, list is freed together with the part
+            try!(check(unsafe { curl_mime_headers(handle, list, 1) }, "headers"));
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Mime {
+    fn drop(&mut self) {
+        // Frees all subparts as well
+        unsafe { curl_mime_free(self.handle) }
+    }
+}
+
+fn check(res: c_int, what: &str) -> Result<(), String> {
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(format!("failed to set mime part {}: {}", what, res))
+    }
+}
+
+// size_t function(char *buffer, size_t size, size_t nitems, void *arg);
+extern "C" fn mime_read_fn(buf: *mut c_char, size: size_t, nitems: size_t, arg: *mut c_void) -> size_t {
+    let source: *mut StreamSource = unsafe { mem::transmute(arg) };
+    if source == ptr::mut_null() {
+        return CURL_READFUNC_ABORT;
+    }
+
+    unsafe {
+        slice::raw::mut_buf_as_slice(buf as *mut u8, (size * nitems) as uint, |dst| {
+            match (*source).reader.read(dst) {
+                Ok(n) => n as size_t,
+                Err(IoError { kind: EndOfFile, .. }) => 0,
+                Err(e) => {
+                    debug!("mime reader failed: {}", e);
+                    CURL_READFUNC_ABORT
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::{Form, Mime};
+    use easy::Curl;
+    use std::io::MemReader;
+
+    #[test]
+    fn build_form() {
+        let mut form = Form::new();
+        form.add_text("name", "value");
+        form.add_data("blob", b"\x00\x01\x02").filename("blob.bin").content_type("application/octet-stream");
+        form.add_reader("stream", box MemReader::new(vec!(1u8, 2, 3)), Some(3)).header("X-Part", "1");
+
+        let mut nested = Form::new();
+        nested.add_text("inner", "value");
+        form.add_multipart("nested", nested);
+
+        let c = Curl::new();
+        let mime = Mime::new(&c, &form).unwrap();
+        assert!(mime.raw() != 0);
+        assert_eq!(mime.attach(&c), 0);
+    }
+
+    #[test]
+    fn reader_consumed_once() {
+        let mut form = Form::new();
+        form.add_reader("stream", box MemReader::new(vec!(1u8)), None);
+
+        let c = Curl::new();
+        assert!(Mime::new(&c, &form).is_ok());
+        assert!(Mime::new(&c, &form).is_err());
+    }
+}
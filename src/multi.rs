@@ -0,0 +1,272 @@
+use libc::{uintptr_t, c_int, c_uint, c_char, c_void, c_long};
+use std::c_str::CString;
+use std::{mem, ptr};
+
+use easy::Curl;
+
+#[repr(C)]
+struct CurlMsg {
+    msg: c_int,
+    easy_handle: uintptr_t,
+    // union { void *whatever; CURLcode result; }
+    result: c_int,
+}
+
+#[allow(dead_code)]
+#[link(name = "curl")]
+extern {
+    fn curl_multi_init() -> uintptr_t;
+    fn curl_multi_cleanup(multi: uintptr_t) -> c_int;
+    fn curl_multi_add_handle(multi: uintptr_t, easy: uintptr_t) -> c_int;
+    fn curl_multi_remove_handle(multi: uintptr_t, easy: uintptr_t) -> c_int;
+    fn curl_multi_perform(multi: uintptr_t, running: *mut c_int) -> c_int;
+    fn curl_multi_wait(multi: uintptr_t, extra_fds: *mut c_void, extra_nfds: c_uint,
+                       timeout_ms: c_int, numfds: *mut c_int) -> c_int;
+    fn curl_multi_info_read(multi: uintptr_t, msgs_in_queue: *mut c_int) -> *const CurlMsg;
+    fn curl_multi_strerror(code: c_int) -> *const c_char;
+    fn curl_multi_setopt(multi: uintptr_t, option: c_int, parameter: uintptr_t) -> c_int;
+    fn curl_multi_socket_action(multi: uintptr_t, socket: c_int, ev_bitmask: c_int,
+                                running: *mut c_int) -> c_int;
+}
+
+static CURLMSG_DONE: c_int = 1;
+
+// CURLMoption values
+static CURLMOPT_SOCKETFUNCTION: c_int = 20000 + 1;
+static CURLMOPT_SOCKETDATA: c_int = 10000 + 2;
+static CURLMOPT_PIPELINING: c_int = 3;
+static CURLMOPT_TIMERFUNCTION: c_int = 20000 + 4;
+static CURLMOPT_TIMERDATA: c_int = 10000 + 5;
+static CURLMOPT_MAX_HOST_CONNECTIONS: c_int = 7;
+static CURLMOPT_MAX_TOTAL_CONNECTIONS: c_int = 13;
+
+static CURLPIPE_MULTIPLEX: c_long = 2;
+
+// CURL_POLL_* values
+static POLL_NONE: c_int = 0;
+static POLL_IN: c_int = 1;
+static POLL_OUT: c_int = 2;
+static POLL_INOUT: c_int = 3;
+
+/// Pass as the socket to `socket_action` when the timer expires
+pub static SOCKET_TIMEOUT: c_int = -1;
+
+// CURL_CSELECT_* bits of `socket_action` events
+pub static CSELECT_IN: c_int = 1;
+pub static CSELECT_OUT: c_int = 2;
+pub static CSELECT_ERR: c_int = 4;
+
+#[deriving(Show)]
+pub struct MultiError {
+    pub code: int,
+    pub message: String
+}
+
+impl MultiError {
+    fn new(code: c_int) -> MultiError {
+        let message = unsafe {
+            CString::new(curl_multi_strerror(code), false).as_str().unwrap_or("").to_string()
+        };
+        MultiError {
+            code: code as int,
+            message: message
+        }
+    }
+}
+
+/// Socket events libcurl waits for
+#[deriving(Clone, Show, PartialEq)]
+pub enum SocketInterest {
+    WaitNone,
+    WaitRead,
+    WaitWrite,
+    WaitReadWrite,
+    /// Socket is not used anymore, stop watching it
+    WaitRemove,
+}
+
+impl SocketInterest {
+    fn from_poll(what: c_int) -> SocketInterest {
+        match what {
+            POLL_NONE => WaitNone,
+            POLL_IN => WaitRead,
+            POLL_OUT => WaitWrite,
+            POLL_INOUT => WaitReadWrite,
+            _ => WaitRemove,
+        }
+    }
+
+    pub fn is_readable(&self) -> bool {
+        *self == WaitRead || *self == WaitReadWrite
+    }
+
+    pub fn is_writable(&self) -> bool {
+        *self == WaitWrite || *self == WaitReadWrite
+    }
+}
+
+/// Event loop side of the socket interface, see `Multi::set_socket_handler`
+///
+/// Calls come from within `socket_action` (and `add`), so they
+/// should only record what to watch.
+pub trait SocketHandler {
+    /// Watch `socket` for `interest` until told otherwise,
+    /// events are reported with `socket_action`
+    fn socket(&mut self, socket: c_int, interest: SocketInterest);
+
+    /// Call `socket_action(SOCKET_TIMEOUT, 0)` in `timeout_ms`
+    /// unless the timer is changed again, `None` cancels it
+    fn timer(&mut self, timeout_ms: Option<uint>);
+}
+
+/// Finished transfer reported by `Multi::info_read`
+pub struct Done {
+    /// Raw handle, compare with `Curl::raw_handle`
+    pub handle: uintptr_t,
+    /// CURLcode of the transfer
+    pub result: uint,
+}
+
+/// libcurl multi handle
+///
+/// Handles added with `add` must be removed before
+/// they are dropped, multi doesn't own them.
+pub struct Multi {
+    handle: uintptr_t,
+    // Double boxed to pass a thin pointer as callback data
+    socket_handler: Option<Box<Box<SocketHandler+Send>>>,
+}
+
+extern "C" fn socket_fn(_: uintptr_t, socket: c_int, what: c_int,
+                        user_data: *mut c_void, _: *mut c_void) -> c_int {
+    unsafe {
+        let handler: &mut Box<SocketHandler+Send> = mem::transmute(user_data);
+        handler.socket(socket, SocketInterest::from_poll(what));
+    }
+    0
+}
+
+extern "C" fn timer_fn(_: uintptr_t, timeout_ms: c_long, user_data: *mut c_void) -> c_int {
+    unsafe {
+        let handler: &mut Box<SocketHandler+Send> = mem::transmute(user_data);
+        handler.timer(if timeout_ms < 0 { None } else { Some(timeout_ms as uint) });
+    }
+    0
+}
+
+impl Multi {
+    pub fn new() -> Multi {
+        Multi {
+            handle: unsafe { curl_multi_init() },
+            socket_handler: None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.handle == 0
+    }
+
+    pub fn raw_handle(&self) -> uintptr_t {
+        self.handle
+    }
+
+    /// Lets transfers to the same host share one HTTP/2 connection
+    pub fn set_multiplex(&self, multiplex: bool) -> Result<(), MultiError> {
+        let value = if multiplex { CURLPIPE_MULTIPLEX } else { 0 };
+        check(unsafe { curl_multi_setopt(self.handle, CURLMOPT_PIPELINING, value as uintptr_t) })
+    }
+
+    /// Connections open to one host at once, 0 is no limit
+    pub fn set_max_host_connections(&self, max: uint) -> Result<(), MultiError> {
+        check(unsafe { curl_multi_setopt(self.handle, CURLMOPT_MAX_HOST_CONNECTIONS, max as uintptr_t) })
+    }
+
+    /// Connections open at once, 0 is no limit
+    pub fn set_max_total_connections(&self, max: uint) -> Result<(), MultiError> {
+        check(unsafe { curl_multi_setopt(self.handle, CURLMOPT_MAX_TOTAL_CONNECTIONS, max as uintptr_t) })
+    }
+
+    /// Switches to the socket interface: transfers are driven by
+    /// `socket_action` as the event loop of `handler` sees events,
+    /// `perform` and `wait` are not used then
+    pub fn set_socket_handler(&mut self, handler: Box<SocketHandler+Send>) -> Result<(), MultiError> {
+        let mut handler = box handler;
+        let user_data: uintptr_t = unsafe { mem::transmute(&mut *handler) };
+        try!(check(unsafe { curl_multi_setopt(self.handle, CURLMOPT_SOCKETFUNCTION, socket_fn as uintptr_t) }));
+        try!(check(unsafe { curl_multi_setopt(self.handle, CURLMOPT_SOCKETDATA, user_data) }));
+        try!(check(unsafe { curl_multi_setopt(self.handle, CURLMOPT_TIMERFUNCTION, timer_fn as uintptr_t) }));
+        try!(check(unsafe { curl_multi_setopt(self.handle, CURLMOPT_TIMERDATA, user_data) }));
+        // Previous handler is dropped only once libcurl points to the new one
+        self.socket_handler = Some(handler);
+        Ok(())
+    }
+
+    /// Reports `events` (`CSELECT_*` bits, 0 if unknown) on `socket`
+    /// or timer expiry with `SOCKET_TIMEOUT`, returns number of
+    /// still running transfers. Finished ones are in `info_read`.
+    pub fn socket_action(&self, socket: c_int, events: c_int) -> Result<uint, MultiError> {
+        let mut running: c_int = 0;
+        try!(check(unsafe { curl_multi_socket_action(self.handle, socket, events, &mut running) }));
+        Ok(running as uint)
+    }
+
+    pub fn add(&self, curl: &Curl) -> Result<(), MultiError> {
+        check(unsafe { curl_multi_add_handle(self.handle, curl.raw_handle()) })
+    }
+
+    pub fn remove(&self, curl: &Curl) -> Result<(), MultiError> {
+        check(unsafe { curl_multi_remove_handle(self.handle, curl.raw_handle()) })
+    }
+
+    /// Does whatever can be done without blocking,
+    /// returns number of still running transfers
+    pub fn perform(&self) -> Result<uint, MultiError> {
+        let mut running: c_int = 0;
+        try!(check(unsafe { curl_multi_perform(self.handle, &mut running) }));
+        Ok(running as uint)
+    }
+
+    /// Waits for activity on any of the transfers
+    /// for at most `timeout_ms`, returns number of ready sockets
+    pub fn wait(&self, timeout_ms: uint) -> Result<uint, MultiError> {
+        let mut numfds: c_int = 0;
+        try!(check(unsafe {
+            curl_multi_wait(self.handle, ptr::mut_null(), 0, timeout_ms as c_int, &mut numfds)
+        }));
+        Ok(numfds as uint)
+    }
+
+    /// Returns next finished transfer, if any
+    pub fn info_read(&self) -> Option<Done> {
+        loop {
+            let mut left: c_int = 0;
+            let msg = unsafe { curl_multi_info_read(self.handle, &mut left) };
+            if msg == ptr::null() {
+                return None;
+            }
+
+            unsafe {
+                if (*msg).msg == CURLMSG_DONE {
+                    return Some(Done {
+                        handle: (*msg).easy_handle,
+                        result: (*msg).result as uint,
+                    });
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Multi {
+    fn drop(&mut self) {
+        unsafe { curl_multi_cleanup(self.handle); }
+    }
+}
+
+fn check(code: c_int) -> Result<(), MultiError> {
+    if code == 0 {
+        Ok(())
+    } else {
+        Err(MultiError::new(code))
+    }
+}
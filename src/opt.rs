@@ -154,8 +154,8 @@ pub static SSH_HOST_PUBLIC_KEY_MD5 : c_int = OBJECTPOINT + 162;
 // pub static OPENSOCKETDATA : c_int = OBJECTPOINT + 164;
 pub static COPYPOSTFIELDS : c_int = OBJECTPOINT + 165;
 pub static PROXY_TRANSFER_MODE : c_int = LONG + 166;
-// pub static SEEKFUNCTION : c_int = FUNCTIONPOINT + 167;
-// pub static SEEKDATA : c_int = OBJECTPOINT + 168;
+pub static SEEKFUNCTION : c_int = FUNCTIONPOINT + 167;
+pub static SEEKDATA : c_int = OBJECTPOINT + 168;
 pub static CRLFILE : c_int = OBJECTPOINT + 169;
 pub static ISSUERCERT : c_int = OBJECTPOINT + 170;
 pub static ADDRESS_SCOPE : c_int = LONG + 171;
@@ -205,6 +205,11 @@ pub static TCP_KEEPIDLE : c_int = LONG + 214;
 pub static TCP_KEEPINTVL : c_int = LONG + 215;
 pub static SSL_OPTIONS : c_int = LONG + 216;
 pub static MAIL_AUTH : c_int = OBJECTPOINT + 217;
+pub static XOAUTH2_BEARER : c_int = OBJECTPOINT + 220;
+pub static PINNEDPUBLICKEY : c_int = OBJECTPOINT + 230;
+pub static SSL_VERIFYSTATUS : c_int = LONG + 232;
+pub static PIPEWAIT : c_int = LONG + 237;
+pub static MIMEPOST : c_int = OBJECTPOINT + 269;
 
   /* three convenient "aliases" that follow the name scheme better */
 pub static WRITEDATA  : c_int = FILE;
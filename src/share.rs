@@ -0,0 +1,100 @@
+use libc::{uintptr_t, c_int, c_void};
+use std::mem;
+use std::rt::mutex::NativeMutex;
+
+use easy::Curl;
+use opt;
+
+#[allow(dead_code)]
+#[link(name = "curl")]
+extern {
+    fn curl_share_init() -> uintptr_t;
+    fn curl_share_setopt(share: uintptr_t, option: c_int, parameter: uintptr_t) -> c_int;
+    fn curl_share_cleanup(share: uintptr_t) -> c_int;
+}
+
+// CURLSHoption values
+static CURLSHOPT_SHARE: c_int = 1;
+static CURLSHOPT_LOCKFUNC: c_int = 3;
+static CURLSHOPT_UNLOCKFUNC: c_int = 4;
+static CURLSHOPT_USERDATA: c_int = 5;
+
+// curl_lock_data values
+pub static LOCK_DATA_COOKIE: c_int = 2;
+pub static LOCK_DATA_DNS: c_int = 3;
+pub static LOCK_DATA_SSL_SESSION: c_int = 4;
+pub static LOCK_DATA_CONNECT: c_int = 5;
+// CURL_LOCK_DATA_LAST, one lock per kind of data below it
+static LOCK_DATA_LAST: uint = 8;
+
+/// libcurl share handle, lets easy handles use the same
+/// connection cache, DNS cache, TLS sessions or cookies
+///
+/// Access is serialised by the lock callbacks, so handles
+/// sharing the data may run in different threads. Handles
+/// must be dropped before the share.
+pub struct Share {
+    handle: uintptr_t,
+    // Mutex per curl_lock_data, passed to callbacks as userdata
+    locks: Box<Vec<NativeMutex>>,
+}
+
+extern "C" fn lock_fn(_: uintptr_t, data: c_int, _: c_int, user_data: *mut c_void) {
+    unsafe {
+        let locks: &Vec<NativeMutex> = mem::transmute(user_data);
+        // Unknown kinds of data, e.g. from a newer libcurl, aren't locked
+        match locks.as_slice().get(data as uint) {
+            Some(lock) => lock.lock_noguard(),
+            None => debug!("No lock for share data {}", data)
+        }
+    }
+}
+
+extern "C" fn unlock_fn(_: uintptr_t, data: c_int, user_data: *mut c_void) {
+    unsafe {
+        let locks: &Vec<NativeMutex> = mem::transmute(user_data);
+        match locks.as_slice().get(data as uint) {
+            Some(lock) => lock.unlock_noguard(),
+            None => debug!("No lock for share data {}", data)
+        }
+    }
+}
+
+impl Share {
+    /// Shares nothing until `share` is called
+    pub fn new() -> Share {
+        let locks = box Vec::from_fn(LOCK_DATA_LAST, |_| unsafe { NativeMutex::new() });
+        let handle = unsafe { curl_share_init() };
+        unsafe {
+            let user_data: uintptr_t = mem::transmute(&*locks);
+            curl_share_setopt(handle, CURLSHOPT_LOCKFUNC, lock_fn as uintptr_t);
+            curl_share_setopt(handle, CURLSHOPT_UNLOCKFUNC, unlock_fn as uintptr_t);
+            curl_share_setopt(handle, CURLSHOPT_USERDATA, user_data);
+        }
+        Share {
+            handle: handle,
+            locks: locks,
+        }
+    }
+
+    /// Starts sharing one of `LOCK_DATA_*`
+    pub fn share(&self, data: c_int) -> int {
+        unsafe { curl_share_setopt(self.handle, CURLSHOPT_SHARE, data as uintptr_t) as int }
+    }
+
+    /// Makes the handle use shared data
+    pub fn attach(&self, curl: &Curl) {
+        curl.setopt(opt::SHARE, self.handle);
+    }
+
+    /// Handle stops using shared data
+    pub fn detach(curl: &Curl) {
+        curl.setopt(opt::SHARE, 0u);
+    }
+}
+
+impl Drop for Share {
+    fn drop(&mut self) {
+        unsafe { curl_share_cleanup(self.handle); }
+    }
+}
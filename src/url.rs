@@ -0,0 +1,342 @@
+use libc::{uintptr_t, c_int, c_uint, c_char};
+use std::c_str::CString;
+use std::fmt;
+use std::ptr;
+
+#[allow(dead_code)]
+#[link(name = "curl")]
+extern {
+    fn curl_url() -> uintptr_t;
+    fn curl_url_cleanup(handle: uintptr_t);
+    fn curl_url_dup(handle: uintptr_t) -> uintptr_t;
+    fn curl_url_get(handle: uintptr_t, what: c_int, part: *mut *mut c_char, flags: c_uint) -> c_int;
+    fn curl_url_set(handle: uintptr_t, what: c_int, part: *const c_char, flags: c_uint) -> c_int;
+    fn curl_url_strerror(code: c_int) -> *const c_char;
+    fn curl_free(ptr: *mut c_char);
+}
+
+// CURLUPart
+static PART_URL: c_int = 0;
+static PART_SCHEME: c_int = 1;
+static PART_USER: c_int = 2;
+static PART_PASSWORD: c_int = 3;
+static PART_HOST: c_int = 5;
+static PART_PORT: c_int = 6;
+static PART_PATH: c_int = 7;
+static PART_QUERY: c_int = 8;
+static PART_FRAGMENT: c_int = 9;
+
+// curl_url_get/curl_url_set flags
+pub static DEFAULT_PORT: c_uint = (1<<0);
+pub static NO_DEFAULT_PORT: c_uint = (1<<1);
+pub static DEFAULT_SCHEME: c_uint = (1<<2);
+pub static NON_SUPPORT_SCHEME: c_uint = (1<<3);
+pub static PATH_AS_IS: c_uint = (1<<4);
+pub static DISALLOW_USER: c_uint = (1<<5);
+pub static URLDECODE: c_uint = (1<<6);
+pub static URLENCODE: c_uint = (1<<7);
+pub static APPENDQUERY: c_uint = (1<<8);
+pub static GUESS_SCHEME: c_uint = (1<<9);
+
+/// CURLUcode of a scheme which isn't supported
+pub static CURLUE_UNSUPPORTED_SCHEME: c_int = 5;
+
+// CURLUcode values which are not errors for getters
+static CURLUE_NO_USER: c_int = 11;
+static CURLUE_NO_PASSWORD: c_int = 12;
+static CURLUE_NO_OPTIONS: c_int = 13;
+static CURLUE_NO_HOST: c_int = 14;
+static CURLUE_NO_PORT: c_int = 15;
+static CURLUE_NO_QUERY: c_int = 16;
+static CURLUE_NO_FRAGMENT: c_int = 17;
+
+#[deriving(Show)]
+pub struct UrlError {
+    pub code: int,
+    pub message: String
+}
+
+impl UrlError {
+    fn new(code: c_int) -> UrlError {
+        let message = unsafe {
+            CString::new(curl_url_strerror(code), false).as_str().unwrap_or("").to_string()
+        };
+        UrlError {
+            code: code as int,
+            message: message
+        }
+    }
+}
+
+/// Parsed URL, backed by libcurl `CURLU` handle
+///
+/// Parsing is done exactly the same way libcurl does it
+/// when performing a request, so whatever is accepted here
+/// is accepted by `opt::URL` too.
+pub struct Url {
+    handle: uintptr_t,
+}
+
+impl Url {
+    /// Parses an absolute URL
+    pub fn parse(url: &str) -> Result<Url, UrlError> {
+        Url::parse_with_flags(url, 0)
+    }
+
+    /// Parses an URL with `curl_url_set` flags,
+    /// i.e. `DEFAULT_SCHEME` to accept `example.com/path`
+    pub fn parse_with_flags(url: &str, flags: c_uint) -> Result<Url, UrlError> {
+        let handle = unsafe { curl_url() };
+        if handle == 0 {
+            // curl_url() fails only on OOM
+            return Err(UrlError::new(7));
+        }
+
+        let url_handle = Url { handle: handle };
+        try!(url_handle.set(PART_URL, Some(url), flags));
+        Ok(url_handle)
+    }
+
+    /// Resolves a reference relatively to this URL as
+    /// described in RFC 3986 section 5.2, absolute references
+    /// replace the base completely
+    pub fn join(&self, reference: &str) -> Result<Url, UrlError> {
+        let res = self.clone();
+        try!(res.set(PART_URL, Some(reference), 0));
+        Ok(res)
+    }
+
+    /// Normalized full URL
+    pub fn as_string(&self) -> String {
+        // URL part is always present for a parsed handle
+        self.get(PART_URL, 0).unwrap_or(String::new())
+    }
+
+    pub fn scheme(&self) -> Option<String> { self.get(PART_SCHEME, 0) }
+    pub fn user(&self) -> Option<String> { self.get(PART_USER, URLDECODE) }
+    pub fn password(&self) -> Option<String> { self.get(PART_PASSWORD, URLDECODE) }
+    pub fn host(&self) -> Option<String> { self.get(PART_HOST, 0) }
+    pub fn path(&self) -> Option<String> { self.get(PART_PATH, 0) }
+    pub fn query(&self) -> Option<String> { self.get(PART_QUERY, 0) }
+    pub fn fragment(&self) -> Option<String> { self.get(PART_FRAGMENT, 0) }
+
+    /// Port, explicit or default for the scheme
+    pub fn port(&self) -> Option<u16> {
+        self.get(PART_PORT, DEFAULT_PORT).and_then(|p| from_str(p.as_slice()))
+    }
+
+    /// Port only if the URL has one
+    pub fn explicit_port(&self) -> Option<u16> {
+        self.get(PART_PORT, 0).and_then(|p| from_str(p.as_slice()))
+    }
+
+    pub fn set_scheme(&mut self, scheme: &str) -> Result<(), UrlError> {
+        self.set(PART_SCHEME, Some(scheme), 0)
+    }
+
+    pub fn set_user(&mut self, user: Option<&str>) -> Result<(), UrlError> {
+        self.set(PART_USER, user, URLENCODE)
+    }
+
+    pub fn set_password(&mut self, password: Option<&str>) -> Result<(), UrlError> {
+        self.set(PART_PASSWORD, password, URLENCODE)
+    }
+
+    pub fn set_host(&mut self, host: &str) -> Result<(), UrlError> {
+        self.set(PART_HOST, Some(host), 0)
+    }
+
+    pub fn set_port(&mut self, port: Option<u16>) -> Result<(), UrlError> {
+        match port {
+            Some(p) => self.set(PART_PORT, Some(p.to_string().as_slice()), 0),
+            None => self.set(PART_PORT, None, 0),
+        }
+    }
+
+    /// Sets already encoded path
+    pub fn set_path(&mut self, path: &str) -> Result<(), UrlError> {
+        self.set(PART_PATH, Some(path), 0)
+    }
+
+    /// Replaces the whole (already encoded) query
+    pub fn set_query(&mut self, query: Option<&str>) -> Result<(), UrlError> {
+        self.set(PART_QUERY, query, 0)
+    }
+
+    pub fn set_fragment(&mut self, fragment: Option<&str>) -> Result<(), UrlError> {
+        self.set(PART_FRAGMENT, fragment, 0)
+    }
+
+    /// Appends `key=value` to the query, both are percent-encoded
+    pub fn append_query(&mut self, key: &str, value: &str) -> Result<(), UrlError> {
+        // With URLENCODE the first '=' is kept as a separator
+        let pair = format!("{}={}", key, value);
+        self.set(PART_QUERY, Some(pair.as_slice()), APPENDQUERY | URLENCODE)
+    }
+
+    fn get(&self, part: c_int, flags: c_uint) -> Option<String> {
+        let mut out: *mut c_char = ptr::mut_null();
+        let res = unsafe { curl_url_get(self.handle, part, &mut out, flags) };
+        match res {
+            0 => unsafe {
+                let s = CString::new(out as *const c_char, false).as_str().map(|s| s.to_string());
+                curl_free(out);
+                s
+            },
+            CURLUE_NO_USER | CURLUE_NO_PASSWORD | CURLUE_NO_OPTIONS | CURLUE_NO_HOST |
+            CURLUE_NO_PORT | CURLUE_NO_QUERY | CURLUE_NO_FRAGMENT => None,
+            _ => {
+                debug!("curl_url_get({}) failed: {}", part, res);
+                None
+            }
+        }
+    }
+
+    fn set(&self, part: c_int, value: Option<&str>, flags: c_uint) -> Result<(), UrlError> {
+        let res = match value {
+            Some(v) => v.with_c_str(|s| unsafe { curl_url_set(self.handle, part, s, flags) }),
+            // NULL clears the part
+            None => unsafe { curl_url_set(self.handle, part, ptr::null(), flags) },
+        };
+        if res == 0 {
+            Ok(())
+        } else {
+            Err(UrlError::new(res))
+        }
+    }
+}
+
+impl Clone for Url {
+    fn clone(&self) -> Url {
+        Url { handle: unsafe { curl_url_dup(self.handle) } }
+    }
+}
+
+impl Drop for Url {
+    fn drop(&mut self) {
+        unsafe { curl_url_cleanup(self.handle) }
+    }
+}
+
+impl fmt::Show for Url {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_string())
+    }
+}
+
+/// Percent-encodes a key or a value as
+/// `application/x-www-form-urlencoded` does,
+/// i.e. space becomes `+`
+pub fn encode_component(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for &b in s.as_bytes().iter() {
+        match b as char {
+            'a'...'z' | 'A'...'Z' | '0'...'9' | '-' | '.' | '_' | '*' => res.push(b as char),
+            ' ' => res.push('+'),
+            _ => res.push_str(format!("%{:02X}", b).as_slice()),
+        }
+    }
+    res
+}
+
+/// Reverses `encode_component`, malformed escapes are kept
+/// as they are and invalid UTF-8 is replaced
+pub fn decode_component(s: &str) -> String {
+    let hex = |b: u8| match b as char {
+        '0'...'9' => Some(b - b'0'),
+        'a'...'f' => Some(b - b'a' + 10),
+        'A'...'F' => Some(b - b'A' + 10),
+        _ => None
+    };
+
+    let bytes = s.as_bytes();
+    let mut res = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => res.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                match (hex(bytes[i + 1]), hex(bytes[i + 2])) {
+                    (Some(h), Some(l)) => {
+                        res.push(h << 4 | l);
+                        i += 2;
+                    },
+                    _ => res.push(b'%')
+                }
+            },
+            b => res.push(b)
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(res.as_slice()).into_string()
+}
+
+/// Encodes pairs as `k1=v1&k2=v2`, keys may repeat
+pub fn encode_pairs<K: Str, V: Str>(pairs: &[(K, V)]) -> String {
+    let mut res = String::new();
+    for &(ref k, ref v) in pairs.iter() {
+        if res.len() > 0 {
+            res.push('&');
+        }
+        res.push_str(encode_component(k.as_slice()).as_slice());
+        res.push('=');
+        res.push_str(encode_component(v.as_slice()).as_slice());
+    }
+    res
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::{Url, encode_pairs, decode_component};
+
+    #[test]
+    fn parse_components() {
+        let url = Url::parse("https://user:pw@example.com:8443/a/b?x=1#frag").unwrap();
+        assert_eq!(url.scheme(), Some("https".to_string()));
+        assert_eq!(url.user(), Some("user".to_string()));
+        assert_eq!(url.password(), Some("pw".to_string()));
+        assert_eq!(url.host(), Some("example.com".to_string()));
+        assert_eq!(url.port(), Some(8443));
+        assert_eq!(url.path(), Some("/a/b".to_string()));
+        assert_eq!(url.query(), Some("x=1".to_string()));
+        assert_eq!(url.fragment(), Some("frag".to_string()));
+
+        let url = Url::parse("http://example.com").unwrap();
+        assert_eq!(url.port(), Some(80));
+        assert_eq!(url.query(), None);
+        assert!(Url::parse("not a url").is_err());
+    }
+
+    #[test]
+    fn join() {
+        let base = Url::parse("http://example.com/a/b/c").unwrap();
+        assert_eq!(base.join("d").unwrap().as_string().as_slice(), "http://example.com/a/b/d");
+        assert_eq!(base.join("../d").unwrap().as_string().as_slice(), "http://example.com/a/d");
+        assert_eq!(base.join("/d").unwrap().as_string().as_slice(), "http://example.com/d");
+        assert_eq!(base.join("?q=1").unwrap().as_string().as_slice(), "http://example.com/a/b/c?q=1");
+        assert_eq!(base.join("https://other.org/x").unwrap().as_string().as_slice(), "https://other.org/x");
+        // base is left untouched
+        assert_eq!(base.as_string().as_slice(), "http://example.com/a/b/c");
+    }
+
+    #[test]
+    fn append_query() {
+        let mut url = Url::parse("http://example.com/?a=1").unwrap();
+        url.append_query("b c", "d&e").unwrap();
+        url.append_query("b c", "f").unwrap();
+        assert_eq!(url.query(), Some("a=1&b+c=d%26e&b+c=f".to_string()));
+    }
+
+    #[test]
+    fn encode() {
+        assert_eq!(encode_pairs([("a b", "1&2"), ("a b", "ü")]).as_slice(), "a+b=1%262&a+b=%C3%BC");
+    }
+
+    #[test]
+    fn decode() {
+        assert_eq!(decode_component("a+b%262%C3%BC").as_slice(), "a b&2ü");
+        assert_eq!(decode_component("100%").as_slice(), "100%");
+        assert_eq!(decode_component("%zz%4").as_slice(), "%zz%4");
+    }
+}
@@ -0,0 +1,431 @@
+use libc::{uintptr_t, c_int, c_uint, c_ulong, c_void, size_t};
+use std::{cmp, mem, os, ptr, str};
+use time;
+
+use easy;
+use easy::Curl;
+use errors::{CURLE_OK, CURLE_AGAIN, CURLE_RECV_ERROR, CURLE_SEND_ERROR, CURLE_GOT_NOTHING,
+             CURLE_OPERATION_TIMEDOUT};
+use info;
+use opt;
+
+#[repr(C)]
+struct WsFrame {
+    age: c_int,
+    flags: c_int,
+    offset: i64,
+    bytesleft: i64,
+    len: size_t,
+}
+
+#[repr(C)]
+struct PollFd {
+    fd: c_int,
+    events: i16,
+    revents: i16,
+}
+
+static POLLIN: i16 = 0x1;
+static POLLOUT: i16 = 0x4;
+static POLLERR: i16 = 0x8;
+static POLLNVAL: i16 = 0x20;
+
+static EINTR: int = 4;
+
+#[allow(dead_code)]
+#[link(name = "curl")]
+extern {
+    fn curl_easy_getinfo(h: uintptr_t, inf: c_int, ptr: *mut c_void) -> c_int;
+    fn curl_ws_recv(h: uintptr_t, buffer: *mut c_void, buflen: size_t,
+                    recv: *mut size_t, meta: *mut *const WsFrame) -> c_uint;
+    fn curl_ws_send(h: uintptr_t, buffer: *const c_void, buflen: size_t,
+                    sent: *mut size_t, fragsize: i64, flags: c_uint) -> c_uint;
+}
+
+extern {
+    fn poll(fds: *mut PollFd, nfds: c_ulong, timeout: c_int) -> c_int;
+}
+
+// Frame flags, see curl_ws_meta
+static CURLWS_TEXT: c_uint = (1<<0);
+static CURLWS_BINARY: c_uint = (1<<1);
+static CURLWS_CONT: c_uint = (1<<2);
+static CURLWS_CLOSE: c_uint = (1<<3);
+static CURLWS_PING: c_uint = (1<<4);
+static CURLWS_PONG: c_uint = (1<<6);
+
+static RECV_BUFFER_SIZE: uint = 16 * 1024;
+
+/// Normal closure, RFC 6455 section 7.4.1
+pub static CLOSE_NORMAL: u16 = 1000;
+
+/// Complete WebSocket message, fragments are already joined
+#[deriving(Show, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    /// libcurl answers pings itself, they are reported for information only
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// Close frame with an optional status code and reason
+    Close(Option<u16>, String),
+}
+
+#[deriving(Show)]
+pub struct WsError {
+    pub code: uint,
+    pub message: String
+}
+
+impl WsError {
+    fn from_code(code: c_uint) -> WsError {
+        WsError {
+            code: code as uint,
+            message: easy::strerror(code as int)
+        }
+    }
+}
+
+/// WebSocket connection on top of libcurl `curl_ws_*` API
+///
+/// Connection is upgraded by a usual `Curl` handle in
+/// `CONNECT_ONLY` mode, so all TLS, proxy and header
+/// options set on the handle are applied to the handshake.
+pub struct WebSocket {
+    curl: Curl,
+    closed: bool,
+    // Data message being reassembled, kept here as
+    // control frames may be returned in the middle of it
+    pending: Vec<u8>,
+    pending_flags: c_uint,
+    timeout_ms: Option<uint>,
+}
+
+/// Blocking iterator over incoming messages,
+/// stops after a close frame or an error
+pub struct Messages<'a> {
+    ws: &'a mut WebSocket,
+    done: bool,
+}
+
+impl WebSocket {
+    /// Connects to `ws://` or `wss://` URL
+    pub fn connect(url: &str) -> Result<WebSocket, WsError> {
+        let curl = Curl::new();
+        curl.setopt(opt::URL, url);
+        curl.setopt(opt::NOSIGNAL, true);
+        WebSocket::upgrade(curl)
+    }
+
+    /// Performs the upgrade using already configured handle,
+    /// `opt::URL` must be set
+    pub fn upgrade(curl: Curl) -> Result<WebSocket, WsError> {
+        // 2 means "connect only, then use curl_ws_* for data"
+        curl.setopt(opt::CONNECT_ONLY, 2i);
+        let res = curl.perform() as c_uint;
+        if res != CURLE_OK {
+            return Err(WsError::from_code(res));
+        }
+
+        Ok(WebSocket {
+            curl: curl,
+            closed: false,
+            pending: Vec::new(),
+            pending_flags: 0,
+            timeout_ms: None,
+        })
+    }
+
+    /// Longest wait of a blocking call for the socket,
+    /// `None` waits forever
+    pub fn set_timeout(&mut self, timeout_ms: Option<uint>) {
+        self.timeout_ms = timeout_ms;
+    }
+
+    pub fn send_text(&mut self, text: &str) -> Result<(), WsError> {
+        self.send_frame(text.as_bytes(), CURLWS_TEXT)
+    }
+
+    pub fn send_binary(&mut self, data: &[u8]) -> Result<(), WsError> {
+        self.send_frame(data, CURLWS_BINARY)
+    }
+
+    pub fn ping(&mut self, payload: &[u8]) -> Result<(), WsError> {
+        self.send_frame(payload, CURLWS_PING)
+    }
+
+    pub fn pong(&mut self, payload: &[u8]) -> Result<(), WsError> {
+        self.send_frame(payload, CURLWS_PONG)
+    }
+
+    /// Sends a message split into frames of at most `fragment_size` bytes
+    pub fn send_fragmented(&mut self, data: &[u8], binary: bool,
+                           fragment_size: uint) -> Result<(), WsError> {
+        let kind = if binary { CURLWS_BINARY } else { CURLWS_TEXT };
+        let size = if fragment_size == 0 { data.len() } else { fragment_size };
+        let mut offset = 0;
+        loop {
+            let end = if offset + size < data.len() { offset + size } else { data.len() };
+            let flags = if end < data.len() { kind | CURLWS_CONT } else { kind };
+            try!(self.send_frame(data.slice(offset, end), flags));
+            if end == data.len() {
+                return Ok(());
+            }
+            offset = end;
+        }
+    }
+
+    pub fn send(&mut self, msg: &Message) -> Result<(), WsError> {
+        match *msg {
+            Text(ref s) => self.send_text(s.as_slice()),
+            Binary(ref b) => self.send_binary(b.as_slice()),
+            Ping(ref p) => self.ping(p.as_slice()),
+            Pong(ref p) => self.pong(p.as_slice()),
+            Close(code, ref reason) => self.close(code, reason.as_slice()),
+        }
+    }
+
+    /// Sends a close frame, the peer answers with its own
+    /// close frame which is returned by `recv`
+    pub fn close(&mut self, code: Option<u16>, reason: &str) -> Result<(), WsError> {
+        self.closed = true;
+        let payload = encode_close(code, reason);
+        self.send_frame(payload.as_slice(), CURLWS_CLOSE)
+    }
+
+    /// Blocks until a complete message arrives
+    pub fn recv(&mut self) -> Result<Message, WsError> {
+        let mut control: Vec<u8> = Vec::new();
+        let mut buf = Vec::from_elem(RECV_BUFFER_SIZE, 0u8);
+
+        loop {
+            let mut received: size_t = 0;
+            let mut meta: *const WsFrame = ptr::null();
+            let res = unsafe {
+                curl_ws_recv(self.curl.raw_handle(), buf.as_mut_ptr() as *mut c_void,
+                             buf.len() as size_t, &mut received, &mut meta)
+            };
+
+            if res == CURLE_AGAIN {
+                try!(self.wait(POLLIN));
+                continue;
+            }
+            if res != CURLE_OK {
+                return Err(WsError::from_code(res));
+            }
+            if meta == ptr::null() {
+                return Err(WsError::from_code(CURLE_RECV_ERROR));
+            }
+
+            let (flags, bytesleft) = unsafe { ((*meta).flags as c_uint, (*meta).bytesleft) };
+            let chunk = buf.slice_to(received as uint);
+
+            // Control frames can be interleaved with fragments
+            // of a data message, so collect them separately
+            if flags & (CURLWS_CLOSE | CURLWS_PING | CURLWS_PONG) != 0 {
+                control.push_all(chunk);
+                if bytesleft > 0 {
+                    continue;
+                }
+                let payload = mem::replace(&mut control, Vec::new());
+                if flags & CURLWS_CLOSE != 0 {
+                    self.closed = true;
+                    let (code, reason) = decode_close(payload.as_slice());
+                    return Ok(Close(code, reason));
+                } else if flags & CURLWS_PING != 0 {
+                    return Ok(Ping(payload));
+                } else {
+                    return Ok(Pong(payload));
+                }
+            }
+
+            if self.pending_flags == 0 {
+                self.pending_flags = flags & (CURLWS_TEXT | CURLWS_BINARY);
+            }
+            self.pending.push_all(chunk);
+
+            if bytesleft == 0 && flags & CURLWS_CONT == 0 {
+                let message = mem::replace(&mut self.pending, Vec::new());
+                let message_flags = mem::replace(&mut self.pending_flags, 0);
+                return if message_flags & CURLWS_TEXT != 0 {
+                    match String::from_utf8(message) {
+                        Ok(s) => Ok(Text(s)),
+                        Err(_) => Err(WsError {
+                            code: CURLE_RECV_ERROR as uint,
+                            message: "text message is not valid UTF-8".to_string()
+                        }),
+                    }
+                } else {
+                    Ok(Binary(message))
+                };
+            }
+        }
+    }
+
+    pub fn messages<'a>(&'a mut self) -> Messages<'a> {
+        Messages {
+            ws: self,
+            done: false,
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    fn send_frame(&mut self, data: &[u8], flags: c_uint) -> Result<(), WsError> {
+        let mut offset = 0;
+        loop {
+            let mut sent: size_t = 0;
+            let rest = data.slice_from(offset);
+            let res = unsafe {
+                curl_ws_send(self.curl.raw_handle(), rest.as_ptr() as *const c_void,
+                             rest.len() as size_t, &mut sent, 0, flags)
+            };
+
+            if res == CURLE_AGAIN {
+                try!(self.wait(POLLOUT));
+                continue;
+            }
+            if res != CURLE_OK {
+                return Err(WsError::from_code(res));
+            }
+
+            offset += sent as uint;
+            if offset >= data.len() {
+                return Ok(());
+            }
+        }
+    }
+
+    // Fails with CURLE_OPERATION_TIMEDOUT if the socket
+    // isn't ready in time, poll errors are recv/send errors
+    fn wait(&self, events: i16) -> Result<(), WsError> {
+        let fail_code = if events == POLLIN { CURLE_RECV_ERROR } else { CURLE_SEND_ERROR };
+        let mut sock: c_int = -1;
+        let res = unsafe {
+            curl_easy_getinfo(self.curl.raw_handle(), info::ACTIVESOCKET,
+                              &mut sock as *mut c_int as *mut c_void)
+        };
+        if res != 0 || sock == -1 {
+            return Err(WsError::from_code(CURLE_GOT_NOTHING));
+        }
+
+        let deadline = self.timeout_ms.map(|ms| time::precise_time_ns() + ms as u64 * 1000000);
+        loop {
+            let timeout = match deadline {
+                Some(deadline) => {
+                    let now = time::precise_time_ns();
+                    ((cmp::max(deadline, now) - now + 999999) / 1000000) as c_int
+                },
+                None => -1
+            };
+            let mut fd = PollFd { fd: sock, events: events, revents: 0 };
+            let res = unsafe { poll(&mut fd, 1, timeout) };
+            if res < 0 {
+                let errno = os::errno();
+                if errno == EINTR {
+                    continue;
+                }
+                return Err(WsError { code: fail_code as uint, message: os::error_string(errno as uint) });
+            }
+            if res == 0 {
+                return Err(WsError::from_code(CURLE_OPERATION_TIMEDOUT));
+            }
+            if fd.revents & (POLLERR | POLLNVAL) != 0 {
+                return Err(WsError {
+                    code: fail_code as uint,
+                    message: "socket error while waiting".to_string()
+                });
+            }
+            // Hang up is reported by libcurl on the next call
+            return Ok(());
+        }
+    }
+}
+
+impl<'a> Iterator<Result<Message, WsError>> for Messages<'a> {
+    fn next(&mut self) -> Option<Result<Message, WsError>> {
+        if self.done {
+            return None;
+        }
+
+        let res = self.ws.recv();
+        match res {
+            Ok(Close(..)) | Err(_) => self.done = true,
+            _ => ()
+        }
+        Some(res)
+    }
+}
+
+fn encode_close(code: Option<u16>, reason: &str) -> Vec<u8> {
+    match code {
+        Some(code) => {
+            let mut payload = vec!((code >> 8) as u8, (code & 0xff) as u8);
+            payload.push_all(reason.as_bytes());
+            payload
+        },
+        // Reason can't be sent without a code
+        None => Vec::new(),
+    }
+}
+
+fn decode_close(payload: &[u8]) -> (Option<u16>, String) {
+    if payload.len() < 2 {
+        return (None, String::new());
+    }
+
+    let code = ((payload[0] as u16) << 8) | (payload[1] as u16);
+    let reason = str::from_utf8(payload.slice_from(2)).unwrap_or("").to_string();
+    (Some(code), reason)
+}
+
+#[cfg(test)]
+mod test
+{
+    use super::{WebSocket, Text, Binary, Close, CLOSE_NORMAL, encode_close, decode_close};
+    use errors::CURLE_OPERATION_TIMEDOUT;
+    use std::os;
+
+    #[test]
+    fn close_payload() {
+        let payload = encode_close(Some(CLOSE_NORMAL), "bye");
+        assert_eq!(payload, vec!(0x03, 0xe8, b'b', b'y', b'e'));
+        assert_eq!(decode_close(payload.as_slice()), (Some(CLOSE_NORMAL), "bye".to_string()));
+        assert_eq!(decode_close([]), (None, "".to_string()));
+    }
+
+    // Needs an echo server, e.g. `websocat -s 8765`,
+    // URL can be overridden by CURL_RS_WS_ECHO
+    #[test]
+    #[ignore]
+    fn echo() {
+        let url = os::getenv("CURL_RS_WS_ECHO").unwrap_or("ws://127.0.0.1:8765/".to_string());
+        let mut ws = WebSocket::connect(url.as_slice()).unwrap();
+
+        ws.send_text("hello").unwrap();
+        assert_eq!(ws.recv().unwrap(), Text("hello".to_string()));
+
+        ws.send_fragmented(b"0123456789", true, 3).unwrap();
+        assert_eq!(ws.recv().unwrap(), Binary(b"0123456789".to_vec()));
+
+        ws.close(Some(CLOSE_NORMAL), "").unwrap();
+        for msg in ws.messages() {
+            match msg.unwrap() {
+                Close(..) => break,
+                _ => ()
+            }
+        }
+    }
+
+    // Same echo server, nothing is sent so recv times out
+    #[test]
+    #[ignore]
+    fn timeout() {
+        let url = os::getenv("CURL_RS_WS_ECHO").unwrap_or("ws://127.0.0.1:8765/".to_string());
+        let mut ws = WebSocket::connect(url.as_slice()).unwrap();
+        ws.set_timeout(Some(100));
+        assert_eq!(ws.recv().err().unwrap().code, CURLE_OPERATION_TIMEDOUT as uint);
+    }
+}